@@ -0,0 +1,204 @@
+use crate::error::ApiError;
+use crate::model::address::Address;
+use crate::model::validation::FieldError;
+use crate::service::address::AddressService;
+use crate::service::location::LocationService;
+use crate::traits::service::{Creator, Getter, Updater};
+use bson::{oid::ObjectId, ordered::OrderedDocument, Bson};
+
+pub fn validate_location(
+  location_service: &LocationService,
+  district_id: i32,
+  neighborhood_id: i32,
+) -> Result<(), ApiError> {
+  match location_service.find_pair(district_id, neighborhood_id)? {
+    Some(_) => Ok(()),
+    None => Err(ApiError::InvalidFields(vec![FieldError::new(
+      "neighborhood_id",
+      "unknown_neighborhood",
+      format!("Neighborhood {} is not in district {}", neighborhood_id, district_id),
+    )])),
+  }
+}
+
+// Addresses store only the ids; names are resolved on read so renames show up everywhere.
+pub fn get_addresses(
+  address_service: &AddressService,
+  location_service: &LocationService,
+  user_id: &ObjectId,
+) -> Result<Vec<OrderedDocument>, ApiError> {
+  let mut addresses = address_service.get_all(user_id)?;
+  let mut district_ids: Vec<i32> = addresses
+    .iter()
+    .filter_map(|address| address.get_i32("district_id").ok())
+    .collect();
+  district_ids.sort();
+  district_ids.dedup();
+  let districts = location_service.get_by_ids(&district_ids)?;
+  for address in addresses.iter_mut() {
+    let district_id = address.get_i32("district_id").ok();
+    let neighborhood_id = address.get_i32("neighborhood_id").ok();
+    let district = districts
+      .iter()
+      .find(|district| Some(district.id) == district_id);
+    if let Some(district) = district {
+      let neighborhood = neighborhood_id.and_then(|id| district.neighborhood(id));
+      address.insert("district_name", district.name.clone());
+      if let Some(neighborhood) = neighborhood {
+        address.insert("neighborhood_name", neighborhood.name.clone());
+      }
+    }
+  }
+  Ok(addresses)
+}
+
+pub fn create_address(
+  address_service: AddressService,
+  address: Address,
+  is_default: bool,
+) -> Result<Bson, ApiError> {
+  let user_id = address.user_id().clone();
+  let id = match address_service.create(&address)?.inserted_id {
+    Bson::ObjectId(id) => id,
+    _ => return Err(ApiError::Internal(String::from("inserted address id is not ObjectId"))),
+  };
+  // the first address a user adds becomes their default
+  if is_default || address_service.find_default(&user_id)?.is_none() {
+    address_service.set_default(&id, &user_id)?;
+  }
+  Ok(Bson::ObjectId(id))
+}
+
+pub fn update_address(
+  address_service: AddressService,
+  address: Address,
+  id: ObjectId,
+  is_default: bool,
+) -> Result<(), ApiError> {
+  if address_service.update(&address, &id)?.matched_count == 0 {
+    return Err(ApiError::NotFound("address_not_found"));
+  }
+  if is_default {
+    address_service.set_default(&id, address.user_id())?;
+  }
+  Ok(())
+}
+
+pub fn make_default(
+  address_service: AddressService,
+  id: ObjectId,
+  user_id: ObjectId,
+) -> Result<(), ApiError> {
+  if !address_service.set_default(&id, &user_id)? {
+    return Err(ApiError::NotFound("address_not_found"));
+  }
+  Ok(())
+}
+
+pub fn delete_address(
+  address_service: AddressService,
+  id: ObjectId,
+  user_id: ObjectId,
+) -> Result<(), ApiError> {
+  let deleted = address_service
+    .soft_delete(&id, &user_id)?
+    .ok_or(ApiError::NotFound("address_not_found"))?;
+  if deleted.get_bool("is_default").unwrap_or(false) {
+    if let Some(latest) = address_service.find_latest(&user_id)? {
+      address_service.set_default(latest.get_object_id("_id")?, &user_id)?;
+    }
+  }
+  Ok(())
+}
+
+// These run against the local MongoDB at DB_URL: `cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::action::order::create_order;
+  use crate::service::basket::BasketService;
+  use crate::service::coupon::CouponService;
+  use crate::service::delivery_zone::DeliveryZoneService;
+  use crate::service::listing::ListingService;
+  use crate::service::order::OrderService;
+  use crate::service::reservation::ReservationService;
+  use crate::service::session::SessionService;
+  use crate::test_support::{new_id, TestDatabase};
+
+  fn address(user_id: &ObjectId, title: &str) -> Address {
+    Address::new(user_id.clone(), "Ayşe", "Yılmaz", title, "Atatürk Cd. No 1", "+905321234567", 1, 1)
+  }
+
+  // Creates an address for its owner and returns its id with the owner's id.
+  fn foreign_address(address_service: &AddressService) -> (ObjectId, ObjectId) {
+    let owner_id = new_id();
+    let id = match create_address(address_service.clone(), address(&owner_id, "Ev"), false).unwrap() {
+      Bson::ObjectId(id) => id,
+      _ => panic!("inserted address id is not ObjectId"),
+    };
+    (id, owner_id)
+  }
+
+  fn is_address_not_found<T>(result: Result<T, ApiError>) -> bool {
+    matches!(result, Err(ApiError::NotFound("address_not_found")))
+  }
+
+  #[test]
+  #[ignore]
+  fn find_refuses_other_users_address() {
+    let database = TestDatabase::new();
+    let address_service = AddressService::new(database.collection("address"));
+    let (id, owner_id) = foreign_address(&address_service);
+
+    assert!(address_service.find_owned(&id, &new_id()).unwrap().is_none());
+    assert!(address_service.find_owned(&id, &owner_id).unwrap().is_some());
+  }
+
+  #[test]
+  #[ignore]
+  fn update_refuses_other_users_address() {
+    let database = TestDatabase::new();
+    let address_service = AddressService::new(database.collection("address"));
+    let (id, owner_id) = foreign_address(&address_service);
+
+    let result = update_address(address_service.clone(), address(&new_id(), "İş"), id.clone(), false);
+    assert!(is_address_not_found(result));
+    let stored = address_service.find_owned(&id, &owner_id).unwrap().unwrap();
+    assert_eq!(stored.get_str("title").unwrap(), "Ev");
+  }
+
+  #[test]
+  #[ignore]
+  fn delete_refuses_other_users_address() {
+    let database = TestDatabase::new();
+    let address_service = AddressService::new(database.collection("address"));
+    let (id, owner_id) = foreign_address(&address_service);
+
+    let result = delete_address(address_service.clone(), id.clone(), new_id());
+    assert!(is_address_not_found(result));
+    assert!(address_service.find_owned(&id, &owner_id).unwrap().is_some());
+  }
+
+  #[test]
+  #[ignore]
+  fn checkout_refuses_other_users_address() {
+    let database = TestDatabase::new();
+    let address_service = AddressService::new(database.collection("address"));
+    let (id, _owner_id) = foreign_address(&address_service);
+
+    let result = create_order(
+      OrderService::new(database.collection("order")),
+      BasketService::new(database.collection("basket")),
+      address_service,
+      ListingService::new(database.collection("listing")),
+      ReservationService::new(database.collection("reservation")),
+      CouponService::new(database.collection("coupon"), database.collection("coupon_redemption")),
+      DeliveryZoneService::new(database.collection("delivery_zone")),
+      SessionService::new(database.database().clone(), database.database().clone()),
+      new_id(),
+      Some(id),
+      0.0,
+    );
+    assert!(is_address_not_found(result));
+  }
+}
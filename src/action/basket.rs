@@ -1,119 +1,431 @@
-use crate::model::basket::{Basket, BasketItem};
+use crate::action::coupon::apply_basket_coupon;
+use crate::action::delivery::apply_address_shipping;
+use crate::action::pricing::basket_summary;
+use crate::action::stock;
+use crate::error::ApiError;
+use crate::model::basket::{Basket, BasketItem, BasketSummary};
+use crate::service::address::AddressService;
 use crate::service::basket::BasketService;
-use bson::oid::ObjectId;
-use mongodb::error::Error;
+use crate::service::coupon::CouponService;
+use crate::service::delivery_zone::DeliveryZoneService;
+use crate::service::listing::ListingService;
+use crate::service::order::OrderService;
+use crate::service::reservation::ReservationService;
+use bson::{from_bson, oid::ObjectId, Bson};
+
+pub fn max_item_count() -> i32 {
+  dotenv!("MAX_ITEM_COUNT")
+    .parse::<i32>()
+    .expect("MAX_ITEM_COUNT is not a number")
+}
+
+fn check_item_count(count: i32) -> Result<(), ApiError> {
+  let max_count = max_item_count();
+  if count < 0 || count > max_count {
+    return Err(ApiError::Validation(format!(
+      "Item count must be between 0 and {}",
+      max_count
+    )));
+  }
+  Ok(())
+}
+
+fn count_in_basket(
+  basket_service: &BasketService,
+  listing_id: &ObjectId,
+  user_id: &ObjectId,
+) -> Result<i32, ApiError> {
+  Ok(match basket_service.find_active(user_id)? {
+    Some(basket) => basket
+      .content()
+      .iter()
+      .find(|item| item.listing_id() == listing_id)
+      .map(|item| item.count() as i32)
+      .unwrap_or(0),
+    None => 0,
+  })
+}
 
 pub fn add_to_basket(
   basket_service: BasketService,
-  user_id: String,
-  product_id: String,
-  seller_id: String,
-  listing_id: String,
-) -> Result<String, String> {
-  match basket_service.get_active(&user_id) {
-    Ok(active_basket_option) => match active_basket_option {
-      Some(active_basket) => user_has_active_basket(
-        &basket_service,
-        &active_basket,
-        &product_id,
-        &seller_id,
-        &listing_id,
-        &user_id,
-      ),
-      None => user_does_not_have_active_basket(
-        &basket_service,
-        &product_id,
-        &seller_id,
-        &listing_id,
-        &user_id,
-      ),
-    },
-    Err(e) => {
-      println!("Error while getting active basket, {:?}", e);
-      Err("Error while getting active basket".to_string())
-    }
+  listing_service: ListingService,
+  reservation_service: ReservationService,
+  user_id: ObjectId,
+  product_id: ObjectId,
+  seller_id: ObjectId,
+  listing_id: ObjectId,
+) -> Result<String, ApiError> {
+  let count = count_in_basket(&basket_service, &listing_id, &user_id)?;
+  check_item_count(count + 1)?;
+  stock::reserve(
+    &listing_service,
+    &reservation_service,
+    &listing_id,
+    &user_id,
+    count + 1,
+  )?;
+
+  match basket_service.get_active(&user_id)? {
+    Some(active_basket) => user_has_active_basket(
+      &basket_service,
+      &active_basket,
+      &product_id,
+      &seller_id,
+      &listing_id,
+      &user_id,
+    ),
+    None => user_does_not_have_active_basket(
+      &basket_service,
+      product_id,
+      seller_id,
+      listing_id,
+      user_id,
+    ),
   }
 }
 
 fn user_has_active_basket(
   basket_service: &BasketService,
   _active_basket: &bson::ordered::OrderedDocument,
-  product_id: &str,
-  seller_id: &str,
-  listing_id: &str,
-  user_id: &str,
-) -> Result<String, String> {
-  match basket_service.update_product_count(listing_id, user_id, 1) {
-    Ok(update) => match update {
-      Some(_doc) => Ok("Product count is incremented successfully".to_string()),
-      None => {
-        // product is not present in basket
-        match basket_service.add_item(product_id, seller_id, listing_id, user_id) {
-          Ok(_r) => Ok("Product is added to basket successfully".to_string()),
-          Err(_e) => Err("Error while adding item".to_string()),
-        }
-      }
-    },
-    Err(e) => {
-      println!("Error while incrementing product count,  {:?}", e);
-      Err("Error while incrementing product count".to_string())
+  product_id: &ObjectId,
+  seller_id: &ObjectId,
+  listing_id: &ObjectId,
+  user_id: &ObjectId,
+) -> Result<String, ApiError> {
+  match basket_service.update_product_count(listing_id, user_id, 1)? {
+    Some(_doc) => Ok("Product count is incremented successfully".to_string()),
+    None => {
+      // product is not present in basket
+      basket_service.add_item(product_id, seller_id, listing_id, user_id)?;
+      Ok("Product is added to basket successfully".to_string())
     }
   }
 }
 
 fn user_does_not_have_active_basket(
   basket_service: &BasketService,
-  product_id: &str,
-  seller_id: &str,
-  listing_id: &str,
-  user_id: &str,
-) -> Result<String, String> {
-  let basket_item = BasketItem::new(
-    ObjectId::with_string(product_id).expect("product_id: Invalid ObjectId string"),
-    ObjectId::with_string(seller_id).expect("seller_id: Invalid ObjectId string"),
-    ObjectId::with_string(listing_id).expect("listing_id: Invalid ObjectId string"),
-    1,
-  );
-  let basket = Basket::new(
-    ObjectId::with_string(user_id).expect("Invalid ObjectId string"),
-    vec![basket_item],
-    true,
-  );
-
-  match basket_service.create(&basket) {
-    Ok(_r) => Ok("Basket is created successfully".to_string()),
-    Err(_e) => Err("Basket is not created successfully".to_string()),
+  product_id: ObjectId,
+  seller_id: ObjectId,
+  listing_id: ObjectId,
+  user_id: ObjectId,
+) -> Result<String, ApiError> {
+  let basket_item = BasketItem::new(product_id, seller_id, listing_id, 1);
+  let basket = Basket::new(user_id, vec![basket_item], true);
+
+  basket_service.create(&basket)?;
+  Ok("Basket is created successfully".to_string())
+}
+
+pub fn set_product_count(
+  basket_service: &BasketService,
+  listing_service: &ListingService,
+  reservation_service: &ReservationService,
+  listing_id: &ObjectId,
+  user_id: &ObjectId,
+  count: i32,
+) -> Result<String, ApiError> {
+  check_item_count(count)?;
+  if count == 0 {
+    return remove_item(basket_service, listing_service, reservation_service, listing_id, user_id);
   }
+  if count_in_basket(basket_service, listing_id, user_id)? == 0 {
+    return Err(ApiError::NotFound("basket_item_not_found"));
+  }
+  stock::reserve(listing_service, reservation_service, listing_id, user_id, count)?;
+  match basket_service.set_product_count(listing_id, user_id, count)? {
+    Some(_document) => Ok("Product count is updated successfully".to_string()),
+    None => Err(ApiError::NotFound("basket_item_not_found")),
+  }
+}
+
+pub fn remove_item(
+  basket_service: &BasketService,
+  listing_service: &ListingService,
+  reservation_service: &ReservationService,
+  listing_id: &ObjectId,
+  user_id: &ObjectId,
+) -> Result<String, ApiError> {
+  if count_in_basket(basket_service, listing_id, user_id)? == 0 {
+    return Err(ApiError::NotFound("basket_item_not_found"));
+  }
+  stock::reserve(listing_service, reservation_service, listing_id, user_id, 0)?;
+  basket_service.remove_product(listing_id, user_id)?;
+  Ok("Product is removed successfully".to_string())
+}
+
+pub fn clear_basket(
+  basket_service: &BasketService,
+  listing_service: &ListingService,
+  reservation_service: &ReservationService,
+  user_id: &ObjectId,
+) -> Result<String, ApiError> {
+  let basket = basket_service
+    .find_active(user_id)?
+    .ok_or(ApiError::NotFound("active_basket_not_found"))?;
+  for item in basket.content() {
+    stock::reserve(listing_service, reservation_service, item.listing_id(), user_id, 0)?;
+  }
+  basket_service.clear(user_id)?;
+  Ok("Basket is cleared successfully".to_string())
+}
+
+pub fn get_summary(
+  basket_service: &BasketService,
+  listing_service: &ListingService,
+  coupon_service: &CouponService,
+  order_service: &OrderService,
+  address_service: &AddressService,
+  delivery_zone_service: &DeliveryZoneService,
+  user_id: &ObjectId,
+  address_id: Option<&ObjectId>,
+) -> Result<BasketSummary, ApiError> {
+  let document = basket_service
+    .get_active(user_id)?
+    .ok_or(ApiError::NotFound("active_basket_not_found"))?;
+  let basket_id = document.get_object_id("_id")?.clone();
+  let basket = from_bson::<Basket>(Bson::Document(document))?;
+  let mut summary = basket_summary(listing_service, basket_id, &basket)?;
+  apply_address_shipping(
+    address_service,
+    delivery_zone_service,
+    &mut summary,
+    user_id,
+    address_id,
+  )?;
+  if let Some(code) = basket.coupon_code() {
+    apply_basket_coupon(coupon_service, order_service, &mut summary, code, user_id)?;
+  }
+  Ok(summary)
 }
 
-pub fn decrement_product_count(
+pub fn merge_baskets(
   basket_service: &BasketService,
-  listing_id: &str,
-  user_id: &str,
-) -> Result<String, Error> {
-  match basket_service.get_product_with_count_one(
-    listing_id.to_string(),
-    user_id.to_string(),
-  ) {
-    Ok(option) => match option {
-      Some(_document) => match basket_service.remove_product(listing_id, user_id) {
-        Ok(_update) => Ok("Product is removed successfuly".to_string()),
-        Err(e) => {
-          println!("product can not be removed: {}", e);
-          Err(e)
+  listing_service: &ListingService,
+  reservation_service: &ReservationService,
+  guest_id: &ObjectId,
+  user_id: &ObjectId,
+) -> Result<String, ApiError> {
+  let guest_basket = match basket_service.find_active(guest_id)? {
+    Some(basket) => basket,
+    None => return Ok("Guest user does not have an active basket".to_string()),
+  };
+  let guest_coupon_code = guest_basket.coupon_code().map(String::from);
+  let max_count = max_item_count();
+  // listings whose summed count went over the limit, with the count they were cut to
+  let mut capped: Vec<(ObjectId, i32)> = vec![];
+
+  match basket_service.find_active(user_id)? {
+    Some(basket) => {
+      for item in guest_basket.into_content() {
+        let own_count = basket
+          .content()
+          .iter()
+          .find(|own_item| own_item.listing_id() == item.listing_id())
+          .map(|own_item| own_item.count() as i32);
+        let count = own_count.unwrap_or(0) + item.count() as i32;
+        if count > max_count {
+          capped.push((item.listing_id().clone(), max_count));
         }
-      },
-      None => match basket_service.update_product_count(listing_id, user_id, -1) {
-        Ok(_document) => Ok("Product count is decremented successfuly".to_string()),
-        Err(e) => {
-          println!("product can not be decremented: {}", e);
-          Err(e)
+        match own_count {
+          Some(_own_count) => {
+            basket_service.set_product_count(item.listing_id(), user_id, count.min(max_count))?;
+          }
+          None => {
+            // product is not present in registered basket
+            let item = BasketItem::new(
+              item.product_id().clone(),
+              item.seller_id().clone(),
+              item.listing_id().clone(),
+              count.min(max_count) as i16,
+            );
+            basket_service.push_item(user_id, &item)?;
+          }
         }
-      },
-    },
-    Err(e) => {
-      println!("Error while getting product, {:?}", e);
-      Err(e)
+      }
+      // the registered basket keeps its own coupon if it has one
+      if let (None, Some(code)) = (basket.coupon_code(), &guest_coupon_code) {
+        basket_service.set_coupon(user_id, code)?;
+      }
+    }
+    None => {
+      let content = guest_basket
+        .into_content()
+        .into_iter()
+        .map(|item| {
+          if item.count() as i32 > max_count {
+            capped.push((item.listing_id().clone(), max_count));
+          }
+          BasketItem::new(
+            item.product_id().clone(),
+            item.seller_id().clone(),
+            item.listing_id().clone(),
+            (item.count() as i32).min(max_count) as i16,
+          )
+        })
+        .collect();
+      basket_service.create(&Basket::new(user_id.clone(), content, true))?;
+      if let Some(code) = &guest_coupon_code {
+        basket_service.set_coupon(user_id, code)?;
+      }
+    }
+  }
+
+  // the merged counts are the sum of both baskets, so guest reservations move over as they are
+  // and only the units cut by the limit are given back
+  stock::transfer(reservation_service, guest_id, user_id)?;
+  for (listing_id, count) in capped {
+    stock::reserve(listing_service, reservation_service, &listing_id, user_id, count)?;
+  }
+  basket_service.delete(guest_id)?;
+  Ok("Guest basket is merged successfully".to_string())
+}
+
+// These run against the local MongoDB at DB_URL: `cargo test -- --ignored`.
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::action::token::{decode_access_token, issue_tokens};
+  use crate::action::user::{create, login};
+  use crate::model::user::AuthUser;
+  use crate::service::token::TokenService;
+  use crate::service::user::UserService;
+  use crate::test_support::{new_id, TestDatabase};
+  use bson::doc;
+
+  struct Services {
+    basket: BasketService,
+    listing: ListingService,
+    reservation: ReservationService,
+  }
+
+  fn services(database: &TestDatabase) -> Services {
+    Services {
+      basket: BasketService::new(database.collection("basket")),
+      listing: ListingService::new(database.collection("listing")),
+      reservation: ReservationService::new(database.collection("reservation")),
     }
   }
+
+  fn create_basket(services: &Services, user_id: &ObjectId, items: &[(&ObjectId, i16)]) {
+    let content = items
+      .iter()
+      .map(|(listing_id, count)| BasketItem::new(new_id(), new_id(), (*listing_id).clone(), *count))
+      .collect();
+    services
+      .basket
+      .create(&Basket::new(user_id.clone(), content, true))
+      .unwrap();
+  }
+
+  fn count_of(basket: &Basket, listing_id: &ObjectId) -> Option<i16> {
+    basket
+      .content()
+      .iter()
+      .find(|item| item.listing_id() == listing_id)
+      .map(|item| item.count())
+  }
+
+  #[test]
+  #[ignore]
+  fn merge_sums_counts_per_listing() {
+    let database = TestDatabase::new();
+    let services = services(&database);
+    let (guest_id, user_id) = (new_id(), new_id());
+    let (shared_id, guest_only_id) = (new_id(), new_id());
+    create_basket(&services, &user_id, &[(&shared_id, 2)]);
+    create_basket(&services, &guest_id, &[(&shared_id, 3), (&guest_only_id, 1)]);
+
+    merge_baskets(&services.basket, &services.listing, &services.reservation, &guest_id, &user_id)
+      .unwrap();
+
+    let basket = services.basket.find_active(&user_id).unwrap().unwrap();
+    assert_eq!(basket.content().len(), 2);
+    assert_eq!(count_of(&basket, &shared_id), Some(5));
+    assert_eq!(count_of(&basket, &guest_only_id), Some(1));
+    assert!(services.basket.find_active(&guest_id).unwrap().is_none());
+  }
+
+  #[test]
+  #[ignore]
+  fn merge_creates_missing_registered_basket_with_guest_coupon() {
+    let database = TestDatabase::new();
+    let services = services(&database);
+    let (guest_id, user_id, listing_id) = (new_id(), new_id(), new_id());
+    create_basket(&services, &guest_id, &[(&listing_id, 2)]);
+    services.basket.set_coupon(&guest_id, "WELCOME").unwrap();
+
+    merge_baskets(&services.basket, &services.listing, &services.reservation, &guest_id, &user_id)
+      .unwrap();
+
+    let basket = services.basket.find_active(&user_id).unwrap().unwrap();
+    assert_eq!(count_of(&basket, &listing_id), Some(2));
+    assert_eq!(basket.coupon_code(), Some("WELCOME"));
+    assert!(services.basket.find_active(&guest_id).unwrap().is_none());
+  }
+
+  #[test]
+  #[ignore]
+  fn merge_caps_counts_at_item_limit() {
+    let database = TestDatabase::new();
+    let services = services(&database);
+    let (guest_id, user_id, listing_id) = (new_id(), new_id(), new_id());
+    database
+      .collection("listing")
+      .insert_one(doc! {"_id": listing_id.clone()}, None)
+      .unwrap();
+    create_basket(&services, &user_id, &[(&listing_id, max_item_count() as i16 - 1)]);
+    create_basket(&services, &guest_id, &[(&listing_id, 3)]);
+
+    merge_baskets(&services.basket, &services.listing, &services.reservation, &guest_id, &user_id)
+      .unwrap();
+
+    let basket = services.basket.find_active(&user_id).unwrap().unwrap();
+    assert_eq!(count_of(&basket, &listing_id), Some(max_item_count() as i16));
+  }
+
+  #[test]
+  #[ignore]
+  fn login_deactivates_guest_basket_and_user() {
+    let database = TestDatabase::new();
+    let services = services(&database);
+    let user_service = UserService::new(database.collection("user"));
+    let token_service = TokenService::new(database.collection("token"));
+    let listing_id = new_id();
+    let guest_id = match user_service.create_anon().unwrap().inserted_id {
+      Bson::ObjectId(id) => id,
+      _ => panic!("inserted guest id is not ObjectId"),
+    };
+    create_basket(&services, &guest_id, &[(&listing_id, 1)]);
+    let guest_tokens = issue_tokens(&token_service, &AuthUser::guest(guest_id.clone())).unwrap();
+    create(
+      user_service.clone(),
+      token_service.clone(),
+      String::from("+905321234567"),
+      String::from("password"),
+      None,
+      None,
+    )
+    .unwrap();
+
+    login(
+      user_service.clone(),
+      services.basket.clone(),
+      services.listing.clone(),
+      services.reservation.clone(),
+      token_service.clone(),
+      String::from("+905321234567"),
+      String::from("password"),
+      Some(guest_id.clone()),
+      Some(guest_tokens.access_token.clone()),
+    )
+    .unwrap();
+
+    assert!(services.basket.find_active(&guest_id).unwrap().is_none());
+    assert!(user_service.find(&guest_id).unwrap().is_none());
+    let guest_claims = decode_access_token(&guest_tokens.access_token, true).unwrap();
+    assert!(token_service.is_access_revoked(&guest_claims.jti).unwrap());
+    let user = user_service.get(&String::from("+905321234567")).unwrap().unwrap();
+    let basket = services.basket.find_active(&user._id).unwrap().unwrap();
+    assert_eq!(count_of(&basket, &listing_id), Some(1));
+  }
 }
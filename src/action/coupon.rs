@@ -0,0 +1,161 @@
+use crate::action::delivery::apply_address_shipping;
+use crate::action::pricing::{basket_summary, round_price};
+use crate::error::ApiError;
+use crate::model::basket::{Basket, BasketSummary};
+use crate::model::coupon::{Coupon, CouponKind, DiscountLine};
+use crate::service::address::AddressService;
+use crate::service::basket::BasketService;
+use crate::service::coupon::CouponService;
+use crate::service::delivery_zone::DeliveryZoneService;
+use crate::service::listing::ListingService;
+use crate::service::order::OrderService;
+use bson::{from_bson, oid::ObjectId, Bson};
+
+// `lines` are the seller and total of every priced basket line, `shipping_fees` the fee of
+// every seller.
+pub fn discount(
+  coupon: &Coupon,
+  lines: &[(&ObjectId, f64)],
+  shipping_fees: &[(&ObjectId, f64)],
+) -> Result<DiscountLine, ApiError> {
+  let now = chrono::Utc::now();
+  if coupon.starts_at.map_or(false, |starts_at| starts_at.0 > now) {
+    return Err(ApiError::Conflict("coupon_not_started"));
+  }
+  if coupon.ends_at.map_or(false, |ends_at| ends_at.0 < now) {
+    return Err(ApiError::Conflict("coupon_expired"));
+  }
+
+  let subtotal: f64 = lines.iter().map(|(_seller_id, total)| total).sum();
+  if coupon
+    .min_basket_value
+    .map_or(false, |min_basket_value| subtotal < min_basket_value)
+  {
+    return Err(ApiError::Conflict("coupon_minimum_not_met"));
+  }
+
+  let eligible: f64 = lines
+    .iter()
+    .filter(|(seller_id, _total)| coupon.seller_id.as_ref().map_or(true, |id| id == *seller_id))
+    .map(|(_seller_id, total)| total)
+    .sum();
+  if eligible <= 0.0 {
+    return Err(ApiError::Conflict("coupon_not_applicable"));
+  }
+
+  let amount = match coupon.kind {
+    CouponKind::Percentage => eligible * coupon.value.min(100.0) / 100.0,
+    CouponKind::FixedAmount => coupon.value.min(eligible),
+    CouponKind::FreeShipping => shipping_fees
+      .iter()
+      .filter(|(seller_id, _fee)| coupon.seller_id.as_ref().map_or(true, |id| id == *seller_id))
+      .map(|(_seller_id, fee)| fee)
+      .sum(),
+  };
+  Ok(DiscountLine {
+    coupon_id: coupon.id.clone(),
+    code: coupon.code.clone(),
+    kind: coupon.kind,
+    seller_id: coupon.seller_id.clone(),
+    amount: round_price(amount),
+  })
+}
+
+pub fn find_usable(
+  coupon_service: &CouponService,
+  order_service: &OrderService,
+  code: &str,
+  user_id: &ObjectId,
+) -> Result<Coupon, ApiError> {
+  let coupon = coupon_service
+    .find_by_code(code)?
+    .ok_or(ApiError::NotFound("coupon_not_found"))?;
+  if coupon
+    .usage_limit
+    .map_or(false, |usage_limit| coupon.used_count >= usage_limit)
+  {
+    return Err(ApiError::Conflict("coupon_usage_limit_reached"));
+  }
+  if let Some(per_user_limit) = coupon.per_user_limit {
+    if order_service.count_coupon_uses(&coupon.id, user_id)? >= per_user_limit as i64 {
+      return Err(ApiError::Conflict("coupon_user_limit_reached"));
+    }
+  }
+  Ok(coupon)
+}
+
+fn summary_discount(summary: &BasketSummary, coupon: &Coupon) -> Result<DiscountLine, ApiError> {
+  let lines: Vec<(&ObjectId, f64)> = summary
+    .sellers
+    .iter()
+    .flat_map(|group| {
+      group
+        .lines
+        .iter()
+        .filter(|line| line.available)
+        .map(move |line| (&group.seller_id, line.line_total))
+    })
+    .collect();
+  let shipping_fees: Vec<(&ObjectId, f64)> = summary
+    .sellers
+    .iter()
+    .map(|group| (&group.seller_id, group.shipping_fee))
+    .collect();
+  discount(coupon, &lines, &shipping_fees)
+}
+
+fn set_discount(summary: &mut BasketSummary, discount_line: DiscountLine) {
+  summary.discount = discount_line.amount;
+  summary.total = round_price(summary.subtotal + summary.shipping_fee - discount_line.amount);
+  summary.coupon = Some(discount_line);
+}
+
+// An applied coupon that stopped being usable is reported on the summary instead of failing it.
+pub fn apply_basket_coupon(
+  coupon_service: &CouponService,
+  order_service: &OrderService,
+  summary: &mut BasketSummary,
+  code: &str,
+  user_id: &ObjectId,
+) -> Result<(), ApiError> {
+  let discount_line = find_usable(coupon_service, order_service, code, user_id)
+    .and_then(|coupon| summary_discount(summary, &coupon));
+  match discount_line {
+    Ok(discount_line) => set_discount(summary, discount_line),
+    Err(ApiError::Database(e)) => return Err(ApiError::Database(e)),
+    Err(e) => summary.coupon_error = Some(e.code().to_string()),
+  }
+  Ok(())
+}
+
+pub fn apply_coupon(
+  basket_service: &BasketService,
+  listing_service: &ListingService,
+  coupon_service: &CouponService,
+  order_service: &OrderService,
+  address_service: &AddressService,
+  delivery_zone_service: &DeliveryZoneService,
+  user_id: &ObjectId,
+  code: &str,
+) -> Result<BasketSummary, ApiError> {
+  let document = basket_service
+    .get_active(user_id)?
+    .ok_or(ApiError::NotFound("active_basket_not_found"))?;
+  let basket_id = document.get_object_id("_id")?.clone();
+  let basket = from_bson::<Basket>(Bson::Document(document))?;
+
+  let coupon = find_usable(coupon_service, order_service, code, user_id)?;
+  let mut summary = basket_summary(listing_service, basket_id, &basket)?;
+  apply_address_shipping(address_service, delivery_zone_service, &mut summary, user_id, None)?;
+  let discount_line = summary_discount(&summary, &coupon)?;
+  basket_service.set_coupon(user_id, &coupon.code)?;
+  set_discount(&mut summary, discount_line);
+  Ok(summary)
+}
+
+pub fn remove_coupon(basket_service: &BasketService, user_id: &ObjectId) -> Result<(), ApiError> {
+  match basket_service.remove_coupon(user_id)?.matched_count {
+    0 => Err(ApiError::NotFound("active_basket_not_found")),
+    _ => Ok(()),
+  }
+}
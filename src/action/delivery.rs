@@ -0,0 +1,128 @@
+use crate::action::pricing::{round_price, shipping_fee};
+use crate::error::ApiError;
+use crate::model::basket::BasketSummary;
+use crate::model::delivery_zone::DeliveryZone;
+use crate::service::address::AddressService;
+use crate::service::delivery_zone::DeliveryZoneService;
+use bson::{oid::ObjectId, ordered::OrderedDocument};
+use std::cmp::Ordering;
+
+fn zone_fee(
+  zones: &[&DeliveryZone],
+  district_id: i32,
+  neighborhood_id: i32,
+  subtotal: f64,
+) -> Result<f64, ApiError> {
+  // the most specific zone wins, then the cheapest one
+  let zone = zones
+    .iter()
+    .filter_map(|zone| {
+      zone
+        .specificity(district_id, neighborhood_id)
+        .map(|specificity| (specificity, zone))
+    })
+    .max_by(|(specificity, zone), (other_specificity, other_zone)| {
+      specificity.cmp(other_specificity).then(
+        other_zone
+          .shipping_fee
+          .partial_cmp(&zone.shipping_fee)
+          .unwrap_or(Ordering::Equal),
+      )
+    })
+    .map(|(_specificity, zone)| zone)
+    .ok_or(ApiError::Conflict("outside_delivery_zone"))?;
+  if subtotal < zone.min_order_amount {
+    return Err(ApiError::Conflict("delivery_minimum_not_met"));
+  }
+  Ok(zone.shipping_fee)
+}
+
+// Returns the fee of every seller in `subtotals`, or why they can not deliver to the address.
+// Sellers without any zone deliver everywhere for the default fee, as before zones existed.
+pub fn shipping_fees(
+  delivery_zone_service: &DeliveryZoneService,
+  address: &OrderedDocument,
+  subtotals: &[(ObjectId, f64)],
+) -> Result<Vec<Result<f64, ApiError>>, ApiError> {
+  let district_id = address.get_i32("district_id")?;
+  let neighborhood_id = address.get_i32("neighborhood_id")?;
+  let seller_ids: Vec<ObjectId> = subtotals
+    .iter()
+    .map(|(seller_id, _subtotal)| seller_id.clone())
+    .collect();
+  let zones = delivery_zone_service.get_for_sellers(&seller_ids)?;
+  Ok(
+    subtotals
+      .iter()
+      .map(|(seller_id, subtotal)| {
+        let seller_zones: Vec<&DeliveryZone> = zones
+          .iter()
+          .filter(|zone| &zone.seller_id == seller_id)
+          .collect();
+        if seller_zones.is_empty() {
+          Ok(shipping_fee())
+        } else {
+          zone_fee(&seller_zones, district_id, neighborhood_id, *subtotal)
+        }
+      })
+      .collect(),
+  )
+}
+
+// Prices shipping of the summary for the given address. Sellers that can not deliver there keep
+// no fee and report why in `delivery_error`.
+pub fn apply_shipping(
+  delivery_zone_service: &DeliveryZoneService,
+  summary: &mut BasketSummary,
+  address: &OrderedDocument,
+) -> Result<(), ApiError> {
+  let subtotals: Vec<(ObjectId, f64)> = summary
+    .sellers
+    .iter()
+    .filter(|group| group.subtotal > 0.0)
+    .map(|group| (group.seller_id.clone(), group.subtotal))
+    .collect();
+  let fees = shipping_fees(delivery_zone_service, address, &subtotals)?;
+  for ((seller_id, _subtotal), fee) in subtotals.iter().zip(fees.into_iter()) {
+    if let Some(group) = summary
+      .sellers
+      .iter_mut()
+      .find(|group| &group.seller_id == seller_id)
+    {
+      match fee {
+        Ok(fee) => group.shipping_fee = fee,
+        Err(e) => {
+          group.shipping_fee = 0.0;
+          group.delivery_error = Some(e.code().to_string());
+        }
+      }
+    }
+  }
+  summary.address_id = Some(address.get_object_id("_id")?.clone());
+  summary.shipping_fee = round_price(summary.sellers.iter().map(|group| group.shipping_fee).sum());
+  summary.total = round_price(summary.subtotal + summary.shipping_fee - summary.discount);
+  Ok(())
+}
+
+// Prices shipping to the given address, or to the default one so the summary total matches what
+// checkout charges. Without a default address the summary keeps the default fee for every seller.
+pub fn apply_address_shipping(
+  address_service: &AddressService,
+  delivery_zone_service: &DeliveryZoneService,
+  summary: &mut BasketSummary,
+  user_id: &ObjectId,
+  address_id: Option<&ObjectId>,
+) -> Result<(), ApiError> {
+  let address = match address_id {
+    Some(address_id) => Some(
+      address_service
+        .find_owned(address_id, user_id)?
+        .ok_or(ApiError::NotFound("address_not_found"))?,
+    ),
+    None => address_service.find_default(user_id)?,
+  };
+  match address {
+    Some(address) => apply_shipping(delivery_zone_service, summary, &address),
+    None => Ok(()),
+  }
+}
@@ -0,0 +1,87 @@
+use crate::error::ApiError;
+use crate::model::listing::{Listing, ListingPatch, Product, ProductPatch};
+use crate::service::listing::ListingService;
+use crate::service::product::ProductService;
+use crate::traits::service::{Creator, Finder, Updater};
+use bson::{from_bson, oid::ObjectId, Bson};
+
+pub fn create_listing(
+  listing_service: ListingService,
+  product_service: ProductService,
+  seller_id: ObjectId,
+  patch: ListingPatch,
+  product: Product,
+) -> Result<Bson, ApiError> {
+  product.validate()?;
+  let product_id =
+    ObjectId::new().map_err(|e| ApiError::Internal(format!("Can not generate id: {}", e)))?;
+  let mut listing = Listing::new(product_id.clone(), seller_id);
+  patch.apply(&mut listing);
+  listing.validate()?;
+
+  product_service.create_with_id(&product_id, &product)?;
+  match listing_service.create(&listing) {
+    Ok(result) => Ok(result.inserted_id),
+    Err(e) => {
+      if let Err(delete_error) = product_service.delete(&product_id) {
+        log::error!("Can not delete orphan product {}: {:?}", product_id, delete_error);
+      }
+      Err(e)
+    }
+  }
+}
+
+pub fn update_listing(
+  listing_service: ListingService,
+  product_service: ProductService,
+  seller_id: ObjectId,
+  listing_id: ObjectId,
+  patch: ListingPatch,
+  product_patch: Option<ProductPatch>,
+) -> Result<(), ApiError> {
+  let document = listing_service
+    .find_owned(&listing_id, &seller_id)?
+    .ok_or(ApiError::NotFound("listing_not_found"))?;
+  let mut listing = from_bson::<Listing>(Bson::Document(document))?;
+  patch.apply(&mut listing);
+  listing.validate()?;
+
+  if let Some(product_patch) = product_patch {
+    // the product is shared with every listing of it, possibly other sellers' ones
+    if listing_service.count_for_product(&listing.product_id)? > 1 {
+      return Err(ApiError::Conflict("product_shared"));
+    }
+    let document = product_service
+      .find(&listing.product_id)?
+      .ok_or(ApiError::NotFound("product_not_found"))?;
+    let mut product = from_bson::<Product>(Bson::Document(document))?;
+    product_patch.apply(&mut product);
+    product.validate()?;
+    product_service.update(&product, &listing.product_id)?;
+  }
+
+  if listing_service.update_owned(&listing, &listing_id)?.matched_count == 0 {
+    return Err(ApiError::NotFound("listing_not_found"));
+  }
+  Ok(())
+}
+
+pub fn delete_listing(
+  listing_service: ListingService,
+  product_service: ProductService,
+  seller_id: ObjectId,
+  listing_id: ObjectId,
+) -> Result<(), ApiError> {
+  let document = listing_service
+    .find_owned(&listing_id, &seller_id)?
+    .ok_or(ApiError::NotFound("listing_not_found"))?;
+  let product_id = document.get_object_id("product_id")?.clone();
+  if listing_service.delete_owned(&listing_id, &seller_id)?.deleted_count == 0 {
+    return Err(ApiError::NotFound("listing_not_found"));
+  }
+  // a product can back several listings, so it goes with the last one
+  if listing_service.count_for_product(&product_id)? == 0 {
+    product_service.delete(&product_id)?;
+  }
+  Ok(())
+}
@@ -1,3 +1,10 @@
 pub mod basket;
 pub mod user;
-pub mod order;
\ No newline at end of file
+pub mod order;
+pub mod token;
+pub mod stock;
+pub mod pricing;
+pub mod coupon;
+pub mod listing;
+pub mod address;
+pub mod delivery;
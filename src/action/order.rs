@@ -1,64 +1,401 @@
-use crate::model::order::{Order, Status};
+use crate::action::coupon::{discount, find_usable};
+use crate::action::delivery::shipping_fees;
+use crate::action::pricing::{price_lines, seller_subtotals, split_by_seller, total_lines};
+use crate::action::stock;
+use crate::error::ApiError;
+use crate::model::basket::Basket;
+use crate::model::coupon::Coupon;
+use crate::model::order::{Order, OrderDocument, OrderLine, SellerOrder, Status, SubOrder};
+use crate::model::user::{AuthUser, Role};
 use crate::service::address::AddressService;
 use crate::service::basket::BasketService;
+use crate::service::coupon::CouponService;
+use crate::service::delivery_zone::DeliveryZoneService;
+use crate::service::listing::ListingService;
 use crate::service::order::OrderService;
-use crate::traits::service::{Creator, Finder};
-
-pub enum CreateOrderResponse {
-  OrderCreated(bson::Bson),
-  ActiveBasketNotFound,
-  AddressNotFound,
-  BasketToDeleteNotFound,
-}
+use crate::service::reservation::ReservationService;
+use crate::service::session::{is_transaction_unsupported, SessionService, Transaction};
+use crate::traits::service::Creator;
+use bson::{from_bson, oid::ObjectId, Bson};
 
 pub fn create_order(
   order_service: OrderService,
   basket_service: BasketService,
   address_service: AddressService,
-  user_id: String,
-  address_id: String,
-) -> Result<CreateOrderResponse, String> {
-  let address_result = address_service.find(&address_id);
-
-  match address_result {
-    Ok(address_option) => match address_option {
-      Some(address) => {
-        let basket_result = basket_service.get_active(&user_id);
-
-        match basket_result {
-          Ok(basket_option) => match basket_option {
-            Some(basket) => {
-              let order = Order::new(
-                bson::oid::ObjectId::with_string(&user_id).expect("Invalid ObjectId string"),
-                basket,
-                address,
-                Status::Taken,
-              );
-
-              let order_result = order_service.create(&order);
-
-              match order_result {
-                Ok(_order) => {
-                  let delete_basket_result = basket_service.delete(&user_id);
-
-                  match delete_basket_result {
-                    Ok(delete_basket_option) => match delete_basket_option {
-                      Some(_basket) => Ok(CreateOrderResponse::OrderCreated(_order.inserted_id)),
-                      None => Ok(CreateOrderResponse::BasketToDeleteNotFound),
-                    },
-                    Err(_e) => Err("Error while deleting basket".to_string()),
-                  }
-                }
-                Err(_e) => Err("Error while creating order".to_string()),
-              }
-            }
-            None => Ok(CreateOrderResponse::ActiveBasketNotFound),
-          },
-          Err(_e) => Err("Error while getting active basket".to_string()),
+  listing_service: ListingService,
+  reservation_service: ReservationService,
+  coupon_service: CouponService,
+  delivery_zone_service: DeliveryZoneService,
+  session_service: SessionService,
+  user_id: ObjectId,
+  address_id: Option<ObjectId>,
+  expected_total: f64,
+) -> Result<bson::Bson, ApiError> {
+  let address = match address_id {
+    Some(address_id) => address_service
+      .find_owned(&address_id, &user_id)?
+      .ok_or(ApiError::NotFound("address_not_found"))?,
+    None => address_service
+      .find_default(&user_id)?
+      .ok_or(ApiError::NotFound("default_address_not_found"))?,
+  };
+  let basket_document = basket_service
+    .get_active(&user_id)?
+    .ok_or(ApiError::NotFound("active_basket_not_found"))?;
+  let basket = from_bson::<Basket>(Bson::Document(basket_document.clone()))?;
+  if basket.content().is_empty() {
+    return Err(ApiError::Validation(String::from("Active basket is empty")));
+  }
+
+  let lines = price_lines(&listing_service, &basket)?;
+  // every seller has to deliver to the address before anything is reserved or charged
+  let subtotals = seller_subtotals(&lines);
+  let fees: Vec<(ObjectId, f64)> = subtotals
+    .iter()
+    .map(|(seller_id, _subtotal)| seller_id.clone())
+    .zip(shipping_fees(&delivery_zone_service, &address, &subtotals)?)
+    .map(|(seller_id, fee)| fee.map(|fee| (seller_id, fee)))
+    .collect::<Result<_, ApiError>>()?;
+  let shipping_fee: f64 = fees.iter().map(|(_seller_id, fee)| fee).sum();
+  let coupon = match basket.coupon_code() {
+    Some(code) => Some(find_usable(&coupon_service, &order_service, code, &user_id)?),
+    None => None,
+  };
+  let discounts = match &coupon {
+    Some(coupon) => {
+      let seller_totals: Vec<(&ObjectId, f64)> = lines
+        .iter()
+        .map(|line| (&line.seller_id, line.line_total))
+        .collect();
+      let seller_fees: Vec<(&ObjectId, f64)> = fees
+        .iter()
+        .map(|(seller_id, fee)| (seller_id, *fee))
+        .collect();
+      vec![discount(coupon, &seller_totals, &seller_fees)?]
+    }
+    None => vec![],
+  };
+  let totals = total_lines(&lines, &discounts, shipping_fee);
+  let sub_orders = split_by_seller(&lines, &discounts, &fees)
+    .into_iter()
+    .map(|(seller_id, seller_lines, seller_totals)| {
+      SubOrder::new(seller_id, seller_lines, seller_totals, Status::Taken, user_id.clone())
+    })
+    .collect();
+  // the client confirms the total it showed, so a price that moved since is never charged silently
+  if (expected_total - totals.total).abs() >= 0.01 {
+    return Err(ApiError::Conflict("price_changed"));
+  }
+
+  for line in &lines {
+    stock::release_expired(&listing_service, &reservation_service, &line.listing_id)?;
+  }
+
+  let basket_id = basket_document.get_object_id("_id")?.clone();
+  let order = Order::new(
+    user_id.clone(),
+    basket_document,
+    address,
+    lines,
+    discounts,
+    totals,
+    sub_orders,
+    Status::Taken,
+  );
+
+  let mut transaction = session_service.start_transaction();
+  let services = CheckOutServices {
+    order: &order_service,
+    basket: &basket_service,
+    listing: &listing_service,
+    reservation: &reservation_service,
+    coupon: &coupon_service,
+  };
+  let coupon = coupon.as_ref();
+  match check_out_in_transaction(&mut transaction, &services, &basket_id, &order, coupon) {
+    Ok(order_id) => {
+      transaction.commit()?;
+      Ok(Bson::ObjectId(order_id))
+    }
+    Err(e) => {
+      transaction.abort();
+      if is_transaction_unsupported(&e) {
+        log::warn!("Transactions are not supported, checking out with compensation");
+        check_out_with_compensation(&services, &basket_id, &order, coupon)
+      } else {
+        Err(e)
+      }
+    }
+  }
+}
+
+struct CheckOutServices<'a> {
+  order: &'a OrderService,
+  basket: &'a BasketService,
+  listing: &'a ListingService,
+  reservation: &'a ReservationService,
+  coupon: &'a CouponService,
+}
+
+fn check_out_in_transaction(
+  transaction: &mut Transaction,
+  services: &CheckOutServices,
+  basket_id: &ObjectId,
+  order: &Order,
+  coupon: Option<&Coupon>,
+) -> Result<ObjectId, ApiError> {
+  if !services.basket.check_out_in(transaction, basket_id)? {
+    return Err(ApiError::Conflict("basket_already_checked_out"));
+  }
+  for line in order.lines() {
+    let reserved = services
+      .reservation
+      .take_in(transaction, &line.listing_id, order.user_id())?;
+    if !services
+      .listing
+      .decrement_stock_in(transaction, &line.listing_id, line.count, reserved)?
+    {
+      return Err(ApiError::Conflict("insufficient_stock"));
+    }
+  }
+  if let Some(coupon) = coupon {
+    services.coupon.redeem_in(transaction, coupon, order.user_id())?;
+  }
+  services.order.create_in(transaction, order)
+}
+
+fn check_out_with_compensation(
+  services: &CheckOutServices,
+  basket_id: &ObjectId,
+  order: &Order,
+  coupon: Option<&Coupon>,
+) -> Result<Bson, ApiError> {
+  // claiming the basket first keeps a retried request from creating a second order
+  if services.basket.check_out(basket_id)?.is_none() {
+    return Err(ApiError::Conflict("basket_already_checked_out"));
+  }
+  let result = decrement_stock(services, order).and_then(|decremented| {
+    redeem_coupon(services, coupon, order.user_id())
+      .and_then(|()| {
+        services
+          .order
+          .create(order)
+          .map(|order_result| order_result.inserted_id)
+          .map_err(|e| {
+            unredeem_coupon(services, coupon, order.user_id());
+            e
+          })
+      })
+      .map_err(|e| {
+        restore_stock(services, &decremented);
+        e
+      })
+  });
+  if result.is_err() {
+    if let Err(reactivate_error) = services.basket.reactivate(basket_id) {
+      log::error!("Can not reactivate basket {}: {:?}", basket_id, reactivate_error);
+    }
+  }
+  result
+}
+
+// Consumed reservations are not given back on failure; the buyer reserves again from the basket.
+fn decrement_stock<'a>(
+  services: &CheckOutServices,
+  order: &'a Order,
+) -> Result<Vec<&'a OrderLine>, ApiError> {
+  let mut decremented = vec![];
+  for line in order.lines() {
+    let reserved = services.reservation.take(&line.listing_id, order.user_id())?;
+    match services
+      .listing
+      .decrement_stock(&line.listing_id, line.count, reserved)
+    {
+      Ok(true) => decremented.push(line),
+      result => {
+        if let Err(release_error) = services.listing.release(&line.listing_id, reserved) {
+          log::error!("Can not release reservation on {}: {:?}", line.listing_id, release_error);
         }
+        restore_stock(services, &decremented);
+        result?;
+        return Err(ApiError::Conflict("insufficient_stock"));
+      }
+    }
+  }
+  Ok(decremented)
+}
+
+fn redeem_coupon(
+  services: &CheckOutServices,
+  coupon: Option<&Coupon>,
+  user_id: &ObjectId,
+) -> Result<(), ApiError> {
+  match coupon {
+    Some(coupon) => services.coupon.redeem(coupon, user_id),
+    None => Ok(()),
+  }
+}
+
+fn unredeem_coupon(services: &CheckOutServices, coupon: Option<&Coupon>, user_id: &ObjectId) {
+  if let Some(coupon) = coupon {
+    if let Err(e) = services.coupon.unredeem(&coupon.id, user_id) {
+      log::error!("Can not give back coupon {}: {:?}", coupon.code, e);
+    }
+  }
+}
+
+fn restore_stock(services: &CheckOutServices, lines: &[&OrderLine]) {
+  for line in lines {
+    if let Err(e) = services.listing.restore_stock(&line.listing_id, line.count) {
+      log::error!("Can not restore stock of {}: {:?}", line.listing_id, e);
+    }
+  }
+}
+
+// Cancelled lines go back on sale. The cancel already happened, so a failure is only logged.
+fn restock_cancelled(listing_service: &ListingService, lines: &[OrderLine]) {
+  for line in lines {
+    if let Err(e) = listing_service.restore_stock(&line.listing_id, line.count) {
+      log::error!("Can not restore stock of {}: {:?}", line.listing_id, e);
+    }
+  }
+}
+
+// A cancelled order no longer counts against its coupons' limits.
+fn release_coupons(coupon_service: &CouponService, order: &OrderDocument) {
+  for discount in &order.discounts {
+    if let Err(e) = coupon_service.unredeem(&discount.coupon_id, &order.user_id) {
+      log::error!("Can not give back coupon {}: {:?}", discount.code, e);
+    }
+  }
+}
+
+pub fn update_status(
+  order_service: OrderService,
+  listing_service: ListingService,
+  coupon_service: CouponService,
+  actor: AuthUser,
+  order_id: ObjectId,
+  next: Status,
+) -> Result<OrderDocument, ApiError> {
+  // sellers move their own sub-orders through `update_sub_order_status`
+  let order = match actor.role {
+    Role::Admin => order_service.find_by_id(&order_id)?,
+    Role::Seller | Role::Customer | Role::Guest => {
+      if next != Status::Cancelled {
+        return Err(ApiError::Forbidden("insufficient_role"));
+      }
+      order_service.find(&order_id, &actor.id)?
+    }
+  }
+  .ok_or(ApiError::NotFound("order_not_found"))?;
+
+  let current = order.status;
+  if !current.can_transition_to(next) {
+    return Err(ApiError::Conflict("invalid_status_transition"));
+  }
+
+  if order.sub_orders.is_empty() {
+    let updated = order_service
+      .update_status(&order_id, current, next, &actor.id)?
+      .ok_or(ApiError::Conflict("order_status_changed"))?;
+    if next == Status::Cancelled {
+      restock_cancelled(&listing_service, &order.lines);
+      release_coupons(&coupon_service, &updated);
+    }
+    return Ok(updated);
+  }
+
+  let mut updated = 0;
+  for sub_order in &order.sub_orders {
+    if sub_order.status.can_transition_to(next)
+      && order_service
+        .update_sub_order_status(&order_id, &sub_order.seller_id, sub_order.status, next, &actor.id)?
+        .is_some()
+    {
+      if next == Status::Cancelled {
+        restock_cancelled(&listing_service, &sub_order.lines);
+      }
+      updated += 1;
+    }
+  }
+  if updated == 0 {
+    return Err(ApiError::Conflict("order_status_changed"));
+  }
+  sync_status(&order_service, &coupon_service, &order_id, &actor.id)
+}
+
+pub fn update_sub_order_status(
+  order_service: OrderService,
+  listing_service: ListingService,
+  coupon_service: CouponService,
+  actor: AuthUser,
+  order_id: ObjectId,
+  next: Status,
+) -> Result<SellerOrder, ApiError> {
+  let seller_id = actor
+    .seller_id
+    .ok_or(ApiError::Forbidden("seller_not_assigned"))?;
+  let order = order_service
+    .find_for_seller(&order_id, &seller_id)?
+    .ok_or(ApiError::NotFound("order_not_found"))?;
+  let (current, lines) = order
+    .sub_order(&seller_id)
+    .map(|sub_order| (sub_order.status, sub_order.lines.clone()))
+    .ok_or(ApiError::NotFound("order_not_found"))?;
+  if !current.can_transition_to(next) {
+    return Err(ApiError::Conflict("invalid_status_transition"));
+  }
+
+  order_service
+    .update_sub_order_status(&order_id, &seller_id, current, next, &actor.id)?
+    .ok_or(ApiError::Conflict("order_status_changed"))?;
+  if next == Status::Cancelled {
+    restock_cancelled(&listing_service, &lines);
+  }
+  let order = sync_status(&order_service, &coupon_service, &order_id, &actor.id)?;
+  let sub_order = order
+    .sub_order(&seller_id)
+    .cloned()
+    .ok_or(ApiError::NotFound("order_not_found"))?;
+  Ok(SellerOrder {
+    id: order.id,
+    address: order.address,
+    sub_order,
+    created_at: order.created_at,
+  })
+}
+
+pub fn get_for_seller(order_service: OrderService, actor: AuthUser) -> Result<Vec<SellerOrder>, ApiError> {
+  let seller_id = actor
+    .seller_id
+    .ok_or(ApiError::Forbidden("seller_not_assigned"))?;
+  order_service.get_for_seller(&seller_id)
+}
+
+// Brings the parent status in line with its sub-orders. Another request may move the parent
+// between the read and the write, so the status is recomputed a few times before giving up.
+// Only the request that moves the parent to cancelled gives its coupons back.
+fn sync_status(
+  order_service: &OrderService,
+  coupon_service: &CouponService,
+  order_id: &ObjectId,
+  changed_by: &ObjectId,
+) -> Result<OrderDocument, ApiError> {
+  for _attempt in 0..3 {
+    let order = order_service
+      .find_by_id(order_id)?
+      .ok_or(ApiError::NotFound("order_not_found"))?;
+    let status = Status::of_sub_orders(&order.sub_orders);
+    if status == order.status {
+      return Ok(order);
+    }
+    if let Some(order) = order_service.update_status(order_id, order.status, status, changed_by)? {
+      if status == Status::Cancelled {
+        release_coupons(coupon_service, &order);
       }
-      None => Ok(CreateOrderResponse::AddressNotFound),
-    },
-    Err(_e) => Err("Error while getting address".to_string()),
+      return Ok(order);
+    }
   }
+  Err(ApiError::Conflict("order_status_changed"))
 }
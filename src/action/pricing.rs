@@ -0,0 +1,251 @@
+use crate::error::ApiError;
+use crate::model::basket::{Basket, BasketLine, BasketSummary, SellerGroup};
+use crate::model::coupon::{CouponKind, DiscountLine};
+use crate::model::order::{OrderLine, OrderTotals};
+use crate::service::listing::ListingService;
+use bson::{oid::ObjectId, ordered::OrderedDocument, Bson};
+
+fn get_price(document: &OrderedDocument, key: &str) -> Result<f64, ApiError> {
+  match document.get(key) {
+    Some(Bson::FloatingPoint(value)) => Ok(*value),
+    Some(Bson::I32(value)) => Ok(*value as f64),
+    Some(Bson::I64(value)) => Ok(*value as f64),
+    _ => Err(ApiError::Internal(format!("{} is not a number", key))),
+  }
+}
+
+pub fn round_price(value: f64) -> f64 {
+  (value * 100.0).round() / 100.0
+}
+
+pub fn shipping_fee() -> f64 {
+  dotenv!("DEFAULT_SHIPPING_FEE")
+    .parse::<f64>()
+    .expect("DEFAULT_SHIPPING_FEE is not a number")
+}
+
+pub fn price_lines(
+  listing_service: &ListingService,
+  basket: &Basket,
+) -> Result<Vec<OrderLine>, ApiError> {
+  let listing_ids = basket
+    .content()
+    .iter()
+    .map(|item| item.listing_id().clone())
+    .collect();
+  let listings = listing_service.find_with_products(listing_ids)?;
+
+  let mut lines = vec![];
+  for item in basket.content() {
+    let listing = listings
+      .iter()
+      .find(|listing| listing.get_object_id("_id").ok() == Some(item.listing_id()))
+      .ok_or(ApiError::Conflict("listing_unavailable"))?;
+    if !listing.get_bool("visible").unwrap_or(false) {
+      return Err(ApiError::Conflict("listing_unavailable"));
+    }
+    let product = listing.get_document("product")?;
+    let unit_price = get_price(product, "price")?;
+    let count = item.count() as i32;
+    lines.push(OrderLine {
+      listing_id: item.listing_id().clone(),
+      product_id: item.product_id().clone(),
+      seller_id: item.seller_id().clone(),
+      name: product.get_str("name").unwrap_or_default().to_string(),
+      count,
+      unit_price,
+      line_total: round_price(unit_price * count as f64),
+    });
+  }
+  Ok(lines)
+}
+
+pub fn total_lines(lines: &[OrderLine], discounts: &[DiscountLine], shipping_fee: f64) -> OrderTotals {
+  let subtotal = round_price(lines.iter().map(|line| line.line_total).sum());
+  let shipping_fee = round_price(shipping_fee);
+  let discount = round_price(discounts.iter().map(|discount| discount.amount).sum());
+  OrderTotals {
+    subtotal,
+    shipping_fee,
+    discount,
+    total: round_price(subtotal + shipping_fee - discount),
+  }
+}
+
+// Groups priced lines by seller, in the order sellers first appear.
+pub fn seller_subtotals(lines: &[OrderLine]) -> Vec<(ObjectId, f64)> {
+  let mut subtotals: Vec<(ObjectId, f64)> = vec![];
+  for line in lines {
+    match subtotals.iter_mut().find(|(seller_id, _subtotal)| seller_id == &line.seller_id) {
+      Some((_seller_id, subtotal)) => *subtotal += line.line_total,
+      None => subtotals.push((line.seller_id.clone(), line.line_total)),
+    }
+  }
+  subtotals
+    .into_iter()
+    .map(|(seller_id, subtotal)| (seller_id, round_price(subtotal)))
+    .collect()
+}
+
+// Splits priced lines into per-seller totals carrying the seller's shipping fee from `fees`.
+// Seller-scoped discounts go to their seller, free shipping covers each seller's own fee and the
+// others are shared in proportion to each seller's subtotal.
+pub fn split_by_seller(
+  lines: &[OrderLine],
+  discounts: &[DiscountLine],
+  fees: &[(ObjectId, f64)],
+) -> Vec<(ObjectId, Vec<OrderLine>, OrderTotals)> {
+  let mut groups: Vec<(ObjectId, Vec<OrderLine>)> = vec![];
+  for line in lines {
+    match groups.iter_mut().find(|(seller_id, _lines)| seller_id == &line.seller_id) {
+      Some((_seller_id, seller_lines)) => seller_lines.push(line.clone()),
+      None => groups.push((line.seller_id.clone(), vec![line.clone()])),
+    }
+  }
+
+  let subtotals: Vec<f64> = groups
+    .iter()
+    .map(|(_seller_id, seller_lines)| round_price(seller_lines.iter().map(|line| line.line_total).sum()))
+    .collect();
+  let shipping_fees: Vec<f64> = groups
+    .iter()
+    .map(|(seller_id, _lines)| {
+      fees
+        .iter()
+        .find(|(fee_seller_id, _fee)| fee_seller_id == seller_id)
+        .map_or(0.0, |(_seller_id, fee)| *fee)
+    })
+    .collect();
+  let subtotal: f64 = subtotals.iter().sum();
+  let mut seller_discounts = vec![0.0; groups.len()];
+  for discount in discounts {
+    match (&discount.seller_id, discount.kind) {
+      (None, CouponKind::FreeShipping) => {
+        for (index, fee) in shipping_fees.iter().enumerate() {
+          seller_discounts[index] += fee;
+        }
+      }
+      (Some(discount_seller_id), _) => {
+        if let Some(index) = groups
+          .iter()
+          .position(|(seller_id, _lines)| seller_id == discount_seller_id)
+        {
+          seller_discounts[index] += discount.amount;
+        }
+      }
+      (None, _) => {
+        let mut remaining = discount.amount;
+        for (index, seller_subtotal) in subtotals.iter().enumerate() {
+          let share = if index + 1 == subtotals.len() || subtotal <= 0.0 {
+            remaining
+          } else {
+            round_price(discount.amount * seller_subtotal / subtotal)
+          };
+          seller_discounts[index] += share;
+          remaining = round_price(remaining - share);
+        }
+      }
+    }
+  }
+
+  groups
+    .into_iter()
+    .zip(subtotals.into_iter().zip(seller_discounts.into_iter()))
+    .zip(shipping_fees.into_iter())
+    .map(|(((seller_id, seller_lines), (subtotal, discount)), shipping_fee)| {
+      let discount = round_price(discount);
+      let totals = OrderTotals {
+        subtotal,
+        shipping_fee,
+        discount,
+        total: round_price(subtotal + shipping_fee - discount),
+      };
+      (seller_id, seller_lines, totals)
+    })
+    .collect()
+}
+
+// Unlike `price_lines`, the summary keeps unavailable listings so the client can show them,
+// but leaves them out of the totals.
+pub fn basket_summary(
+  listing_service: &ListingService,
+  basket_id: ObjectId,
+  basket: &Basket,
+) -> Result<BasketSummary, ApiError> {
+  let listing_ids = basket
+    .content()
+    .iter()
+    .map(|item| item.listing_id().clone())
+    .collect();
+  let listings = listing_service.find_with_products(listing_ids)?;
+
+  let mut sellers: Vec<SellerGroup> = vec![];
+  for item in basket.content() {
+    let listing = listings
+      .iter()
+      .find(|listing| listing.get_object_id("_id").ok() == Some(item.listing_id()));
+    let product = listing.and_then(|listing| listing.get_document("product").ok());
+    let available = listing
+      .map(|listing| listing.get_bool("visible").unwrap_or(false))
+      .unwrap_or(false);
+    let unit_price = match product {
+      Some(product) => get_price(product, "price")?,
+      None => 0.0,
+    };
+    let count = item.count() as i32;
+    let line = BasketLine {
+      listing_id: item.listing_id().clone(),
+      product_id: item.product_id().clone(),
+      name: product
+        .and_then(|product| product.get_str("name").ok())
+        .unwrap_or_default()
+        .to_string(),
+      image_url: product
+        .and_then(|product| product.get_str("image_url").ok())
+        .map(String::from),
+      unit_price,
+      old_price: product.and_then(|product| get_price(product, "old_price").ok()),
+      count,
+      line_total: round_price(unit_price * count as f64),
+      available,
+    };
+
+    match sellers
+      .iter_mut()
+      .find(|group| &group.seller_id == item.seller_id())
+    {
+      Some(group) => group.lines.push(line),
+      None => sellers.push(SellerGroup {
+        seller_id: item.seller_id().clone(),
+        lines: vec![line],
+        subtotal: 0.0,
+        shipping_fee: 0.0,
+        delivery_error: None,
+      }),
+    }
+  }
+
+  // every seller ships at the default fee until `apply_shipping` prices an address
+  let mut item_count = 0;
+  for group in sellers.iter_mut() {
+    let available_lines = group.lines.iter().filter(|line| line.available);
+    group.subtotal = round_price(available_lines.clone().map(|line| line.line_total).sum());
+    let group_count = available_lines.map(|line| line.count).sum::<i32>();
+    group.shipping_fee = if group_count > 0 { shipping_fee() } else { 0.0 };
+    item_count += group_count;
+  }
+  let subtotal = round_price(sellers.iter().map(|group| group.subtotal).sum());
+  let shipping_fee = round_price(sellers.iter().map(|group| group.shipping_fee).sum());
+  Ok(BasketSummary {
+    id: basket_id,
+    sellers,
+    address_id: None,
+    item_count,
+    subtotal,
+    shipping_fee,
+    coupon: None,
+    coupon_error: None,
+    discount: 0.0,
+    total: round_price(subtotal + shipping_fee),
+  })
+}
@@ -0,0 +1,69 @@
+use crate::error::ApiError;
+use crate::service::listing::ListingService;
+use crate::service::reservation::ReservationService;
+use bson::{oid::ObjectId, UtcDateTime};
+
+fn reservation_expiry() -> UtcDateTime {
+  let ttl = dotenv!("RESERVATION_TTL_SECONDS")
+    .parse::<i64>()
+    .expect("RESERVATION_TTL_SECONDS is not a number");
+  UtcDateTime(chrono::Utc::now() + chrono::Duration::seconds(ttl))
+}
+
+pub fn release_expired(
+  listing_service: &ListingService,
+  reservation_service: &ReservationService,
+  listing_id: &ObjectId,
+) -> Result<(), ApiError> {
+  while let Some(count) = reservation_service.take_expired(listing_id)? {
+    listing_service.release(listing_id, count)?;
+  }
+  Ok(())
+}
+
+// Makes the user hold exactly `count` units of the listing, reserving or releasing the difference.
+// Units are reserved before the reservation claims them and released only after it gave them up,
+// so a request racing on the same reservation never leaves the listing counting units nobody holds.
+pub fn reserve(
+  listing_service: &ListingService,
+  reservation_service: &ReservationService,
+  listing_id: &ObjectId,
+  user_id: &ObjectId,
+  count: i32,
+) -> Result<(), ApiError> {
+  release_expired(listing_service, reservation_service, listing_id)?;
+  for _attempt in 0..3 {
+    let held = reservation_service.count_for(listing_id, user_id)?;
+    if count > held {
+      if !listing_service.reserve(listing_id, count - held)? {
+        return Err(ApiError::Conflict("insufficient_stock"));
+      }
+      if !reservation_service.swap(listing_id, user_id, held, count, reservation_expiry())? {
+        listing_service.release(listing_id, count - held)?;
+        continue;
+      }
+    } else if !reservation_service.swap(listing_id, user_id, held, count, reservation_expiry())? {
+      continue;
+    } else if count < held {
+      listing_service.release(listing_id, held - count)?;
+    }
+    return Ok(());
+  }
+  Err(ApiError::Conflict("reservation_changed"))
+}
+
+pub fn transfer(
+  reservation_service: &ReservationService,
+  from_user_id: &ObjectId,
+  to_user_id: &ObjectId,
+) -> Result<(), ApiError> {
+  while let Some(reservation) = reservation_service.take_any(from_user_id)? {
+    reservation_service.add(
+      &reservation.listing_id,
+      to_user_id,
+      reservation.count,
+      reservation_expiry(),
+    )?;
+  }
+  Ok(())
+}
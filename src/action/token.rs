@@ -0,0 +1,113 @@
+use crate::error::ApiError;
+use crate::model::token::{RefreshToken, TokenPair};
+use crate::model::user::{AuthUser, Claims};
+use crate::service::token::TokenService;
+use crate::service::user::UserService;
+use bson::UtcDateTime;
+use chrono::{Duration, TimeZone, Utc};
+use jsonwebtoken::{decode, encode, errors, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::{distributions::Alphanumeric, Rng};
+
+fn random_string(length: usize) -> String {
+  rand::thread_rng()
+    .sample_iter(&Alphanumeric)
+    .take(length)
+    .collect()
+}
+
+fn ttl(seconds: &str) -> Duration {
+  Duration::seconds(seconds.parse::<i64>().expect("token ttl is not a number"))
+}
+
+fn encode_access_token(user: &AuthUser) -> Result<String, ApiError> {
+  let now = Utc::now();
+  let claims = Claims {
+    sub: user.id.to_string(),
+    role: user.role,
+    seller_id: user.seller_id.as_ref().map(|seller_id| seller_id.to_string()),
+    exp: (now + ttl(dotenv!("ACCESS_TOKEN_TTL_SECONDS"))).timestamp(),
+    iat: now.timestamp(),
+    jti: random_string(24),
+  };
+  encode(
+    &Header::default(),
+    &claims,
+    &EncodingKey::from_secret(dotenv!("JWT_SECRET").as_ref()),
+  )
+  .map_err(|e| ApiError::Internal(format!("Can not encode token: {}", e)))
+}
+
+pub fn decode_access_token(token: &str, validate_exp: bool) -> Result<Claims, ApiError> {
+  let validation = Validation {
+    leeway: 0,
+    validate_exp,
+    validate_nbf: false,
+    iss: None,
+    sub: None,
+    aud: None,
+    algorithms: vec![Algorithm::HS256],
+  };
+  match decode::<Claims>(
+    token,
+    &DecodingKey::from_secret(dotenv!("JWT_SECRET").as_ref()),
+    &validation,
+  ) {
+    Ok(decoded_token) => Ok(decoded_token.claims),
+    Err(e) => match e.kind() {
+      errors::ErrorKind::ExpiredSignature => Err(ApiError::Unauthorized("token_expired")),
+      _ => Err(ApiError::Unauthorized("invalid_token")),
+    },
+  }
+}
+
+pub fn issue_tokens(token_service: &TokenService, user: &AuthUser) -> Result<TokenPair, ApiError> {
+  let access_token = encode_access_token(user)?;
+  let refresh_token = random_string(48);
+  let expires_at = Utc::now() + ttl(dotenv!("REFRESH_TOKEN_TTL_SECONDS"));
+  token_service.create_refresh(&RefreshToken::new(
+    user.id.clone(),
+    &refresh_token,
+    UtcDateTime(expires_at),
+  ))?;
+  Ok(TokenPair {
+    access_token,
+    refresh_token,
+  })
+}
+
+pub fn refresh(
+  token_service: &TokenService,
+  user_service: &UserService,
+  refresh_token: &str,
+) -> Result<TokenPair, ApiError> {
+  // refresh tokens are single use, every refresh rotates the pair
+  let stored = token_service
+    .use_refresh(refresh_token)?
+    .ok_or(ApiError::Unauthorized("invalid_refresh_token"))?;
+  // role is read again so that promotions and demotions apply on refresh
+  let user = user_service
+    .find(&stored.user_id)?
+    .ok_or(ApiError::Unauthorized("invalid_refresh_token"))?;
+  issue_tokens(token_service, &user.auth_user())
+}
+
+pub fn logout(
+  token_service: &TokenService,
+  access_token: Option<String>,
+  refresh_token: Option<String>,
+) -> Result<(), ApiError> {
+  if let Some(refresh_token) = refresh_token {
+    token_service.revoke_refresh(&refresh_token)?;
+  }
+  if let Some(access_token) = access_token {
+    revoke_access_token(token_service, &access_token)?;
+  }
+  Ok(())
+}
+
+pub fn revoke_access_token(token_service: &TokenService, access_token: &str) -> Result<(), ApiError> {
+  if let Ok(claims) = decode_access_token(access_token, false) {
+    token_service.revoke_access(&claims.jti, UtcDateTime(Utc.timestamp(claims.exp, 0)))?;
+  }
+  Ok(())
+}
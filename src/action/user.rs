@@ -1,185 +1,134 @@
+use crate::action::basket::merge_baskets;
+use crate::action::stock;
+use crate::action::token::{issue_tokens, revoke_access_token};
+use crate::error::ApiError;
 use crate::model::basket::{Basket, BasketItem};
-use crate::model::user::{Claims, User};
+use crate::model::token::TokenPair;
+use crate::model::user::{AuthUser, User, UserDocument};
+use crate::model::validation::phone_forms;
 use crate::service::basket::BasketService;
+use crate::service::listing::ListingService;
+use crate::service::reservation::ReservationService;
+use crate::service::token::TokenService;
 use crate::service::user::UserService;
 use crate::traits::service::Creator;
 use bcrypt::hash;
 use bcrypt::verify;
 use bson::oid::ObjectId;
-use bson::{from_bson, to_bson};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use serde::Deserialize;
 
 pub fn create_anon_with_basket(
   user_service: UserService,
   basket_service: BasketService,
-  product_id: String,
-  seller_id: String,
-  listing_id: String,
-) -> Result<String, String> {
-  match user_service.create_anon() {
-    Ok(user_result) => match user_result.inserted_id {
-      bson::Bson::ObjectId(id) => {
-        let token = get_guest_user_token(id.to_string());
-        let cookie = format!("access_token={}", token);
+  listing_service: ListingService,
+  reservation_service: ReservationService,
+  token_service: TokenService,
+  product_id: ObjectId,
+  seller_id: ObjectId,
+  listing_id: ObjectId,
+) -> Result<TokenPair, ApiError> {
+  match user_service.create_anon()?.inserted_id {
+    bson::Bson::ObjectId(id) => {
+      stock::reserve(&listing_service, &reservation_service, &listing_id, &id, 1)?;
+      let tokens = issue_tokens(&token_service, &AuthUser::guest(id.clone()))?;
 
-        let basket_item = BasketItem::new(
-          ObjectId::with_string(&product_id).expect("product_id: Invalid ObjectId string"),
-          ObjectId::with_string(&seller_id).expect("seller_id: Invalid ObjectId string"),
-          ObjectId::with_string(&listing_id).expect("listing_id: Invalid ObjectId string"),
-          1,
-        );
-        let basket = Basket::new(
-          ObjectId::with_string(&id.to_string()).expect("Invalid ObjectId string"),
-          vec![basket_item],
-          true,
-        );
+      let basket_item = BasketItem::new(product_id, seller_id, listing_id, 1);
+      let basket = Basket::new(id, vec![basket_item], true);
 
-        match basket_service.create(&basket) {
-          Ok(_basket_result) => Ok(cookie),
-          Err(_e) => Err("Error while creating basket for anon user, {:?}".to_string()),
-        }
-      }
-      _ => Err("Error: inserted anon user id is not ObjectId".to_string()),
-    },
-    Err(_e) => Err("Error while creating anon user: {:?}".to_string()),
+      basket_service.create(&basket)?;
+      Ok(tokens)
+    }
+    _ => Err(ApiError::Internal("inserted anon user id is not ObjectId".to_string())),
   }
 }
 
-fn get_registered_user_token(id: String) -> String {
-  let claims = Claims {
-    sub: id,
-    user_type: String::from("registered"),
-  };
-  let token = encode(
-    &Header::default(),
-    &claims,
-    &EncodingKey::from_secret(dotenv!("JWT_SECRET").as_ref()),
-  )
-  .unwrap();
-  token
-}
-
-fn get_guest_user_token(id: String) -> String {
-  let claims = Claims {
-    sub: id,
-    user_type: String::from("guest"),
-  };
-  let token = encode(
-    &Header::default(),
-    &claims,
-    &EncodingKey::from_secret(dotenv!("JWT_SECRET").as_ref()),
-  )
-  .unwrap();
-  token
-}
-
-pub enum UserCreateResult {
-  UserAlreadyExists,
-  UserCreated(String),
-  GuestUserNotRegistered,
+fn hash_password(password: &str) -> Result<String, ApiError> {
+  hash(password, 4).map_err(|e| ApiError::Internal(format!("Can not hash password: {}", e)))
 }
 
 pub fn create(
   user_service: UserService,
+  token_service: TokenService,
   phone: String,
   password: String,
-  user_id_option: Option<String>,
-) -> Result<UserCreateResult, String> {
-  match user_service.get(&phone) {
-    Ok(user_result) => match user_result {
-      Some(_user) => Ok(UserCreateResult::UserAlreadyExists),
-      None => match user_id_option {
-        Some(user_id) => {
-          let hashed = hash(&password, 4).unwrap();
-          match user_service.register(&user_id, &phone, &hashed) {
-            Ok(user_result) => {
-              if user_result.modified_count == 1 {
-                let token = get_registered_user_token(user_id.to_string());
-                let cookie = format!("access_token={}", token);
-                Ok(UserCreateResult::UserCreated(cookie))
-              } else {
-                Ok(UserCreateResult::GuestUserNotRegistered)
-              }
-            }
-            Err(_e) => Err("Error while registering user".to_string()),
-          }
-        }
-        None => {
-          let hashed = hash(&password, 4).unwrap();
-          let user = User::new(&phone, &hashed);
-          match user_service.create(&user) {
-            Ok(user_result) => match user_result.inserted_id {
-              bson::Bson::ObjectId(id) => {
-                let token = get_registered_user_token(id.to_string());
-                let cookie = format!("access_token={}", token);
-                Ok(UserCreateResult::UserCreated(cookie))
-              }
-              _ => Err("Error: inserted user id is not type of ObjectId".to_string()),
-            },
-            Err(_e) => Err("Error while creating user".to_string()),
-          }
+  user_id_option: Option<ObjectId>,
+  guest_access_token: Option<String>,
+) -> Result<TokenPair, ApiError> {
+  if find_by_phone(&user_service, &phone)?.is_some() {
+    return Err(ApiError::Conflict("user_already_exists"));
+  }
+  match user_id_option {
+    Some(user_id) => {
+      let hashed = hash_password(&password)?;
+      let user_result = user_service.register(&user_id, &phone, &hashed)?;
+      if user_result.modified_count == 1 {
+        // guest tokens must not outlive the registration
+        token_service.revoke_all_refresh(&user_id)?;
+        if let Some(access_token) = guest_access_token {
+          revoke_access_token(&token_service, &access_token)?;
         }
-      },
-    },
-    Err(_e) => Err("Error while getting user".to_string()),
+        issue_tokens(&token_service, &AuthUser::customer(user_id))
+      } else {
+        Err(ApiError::Conflict("guest_user_not_registered"))
+      }
+    }
+    None => {
+      let hashed = hash_password(&password)?;
+      let user = User::new(&phone, &hashed);
+      match user_service.create(&user)?.inserted_id {
+        bson::Bson::ObjectId(id) => issue_tokens(&token_service, &AuthUser::customer(id)),
+        _ => Err(ApiError::Internal("inserted user id is not type of ObjectId".to_string())),
+      }
+    }
   }
 }
 
-pub enum LoginResult {
-  Verified(String),
-  UserNotExists,
-  NotVerified,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-struct UserJson {
-  _id: bson::oid::ObjectId,
-  phone: String,
-  password: String,
+// Registration stores phones in E.164, older accounts keep the form they registered with.
+fn find_by_phone(user_service: &UserService, phone: &str) -> Result<Option<UserDocument>, ApiError> {
+  for form in phone_forms(phone) {
+    if let Some(user) = user_service.get(&form)? {
+      return Ok(Some(user));
+    }
+  }
+  Ok(None)
 }
 
 pub fn login(
   user_service: UserService,
+  basket_service: BasketService,
+  listing_service: ListingService,
+  reservation_service: ReservationService,
+  token_service: TokenService,
   phone: String,
   password: String,
-  user_id_option: Option<String>,
-  user_type_option: Option<String>,
-) -> Result<LoginResult, String> {
-  match user_service.get(&phone) {
-    Ok(user_option) => match user_option {
-      Some(user_document) => {
-        let user_bson = to_bson(&user_document).unwrap();
-        let user = from_bson::<UserJson>(user_bson).unwrap();
-        let verify_result = verify(&password, &user.password);
+  guest_id_option: Option<ObjectId>,
+  guest_access_token: Option<String>,
+) -> Result<TokenPair, ApiError> {
+  let user = find_by_phone(&user_service, &phone)?
+    .ok_or(ApiError::NotFound("user_not_found"))?;
+  let hashed = user
+    .password
+    .as_ref()
+    .ok_or(ApiError::Internal(String::from("registered user has no password")))?;
+  let verified = verify(&password, hashed)
+    .map_err(|e| ApiError::Internal(format!("Can not verify password: {}", e)))?;
+  if !verified {
+    return Err(ApiError::Unauthorized("wrong_password"));
+  }
 
-        match verify_result {
-          Ok(verified) => {
-            if verified == true {
-              match user_id_option {
-                Some(_guest_id) => {
-                  if user_type_option.unwrap() == "guest" {
-                    // TODO: merge basket
-                  }
-                  let token = get_registered_user_token(user._id.to_string());
-                  let cookie = format!("access_token={}; path=/", token);
-                  Ok(LoginResult::Verified(cookie))
-                }
-                None => {
-                  let token = get_registered_user_token(user._id.to_string());
-                  let cookie = format!("access_token={}; path=/", token);
-                  Ok(LoginResult::Verified(cookie))
-                }
-              }
-            } else {
-              Ok(LoginResult::NotVerified)
-            }
-          }
-          Err(_e) => Err("Error while verifying".to_string()),
-        }
-      }
-      None => Ok(LoginResult::UserNotExists),
-    },
-    Err(_e) => Err("Error while getting user".to_string()),
+  if let Some(guest_id) = guest_id_option {
+    merge_baskets(
+      &basket_service,
+      &listing_service,
+      &reservation_service,
+      &guest_id,
+      &user._id,
+    )?;
+    user_service.deactivate_guest(&guest_id, &user._id)?;
+    // the guest is deactivated, none of its tokens may be used again
+    token_service.revoke_all_refresh(&guest_id)?;
+    if let Some(access_token) = guest_access_token {
+      revoke_access_token(&token_service, &access_token)?;
+    }
   }
+  issue_tokens(&token_service, &user.auth_user())
 }
@@ -1,7 +1,14 @@
+use crate::action::address::{
+  create_address, delete_address, get_addresses, make_default, update_address, validate_location,
+};
+use crate::error::ApiError;
+use crate::middleware::validation::Valid;
 use crate::model::address::Address;
-use crate::traits::service::{Creator, Getter, Updater};
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use bson::oid::ObjectId;
+use crate::model::object_id::ObjectIdParam;
+use crate::model::user::AuthUser;
+use crate::model::validation::{is_name_char, Validator};
+use crate::traits::validate::Validate;
+use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Debug, Clone)]
@@ -13,6 +20,38 @@ pub struct CreateAddressBody {
   phone: String,
   district_id: i32,
   neighborhood_id: i32,
+  #[serde(default)]
+  is_default: bool,
+}
+
+impl Validate for CreateAddressBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validator
+      .string("name", &mut self.name)
+      .trimmed()
+      .not_empty()
+      .length(1, 50)
+      .chars(is_name_char, "letters, spaces, dots, dashes and apostrophes");
+    validator
+      .string("surname", &mut self.surname)
+      .trimmed()
+      .not_empty()
+      .length(1, 50)
+      .chars(is_name_char, "letters, spaces, dots, dashes and apostrophes");
+    validator
+      .string("title", &mut self.title)
+      .trimmed()
+      .not_empty()
+      .length(1, 50);
+    validator
+      .string("text", &mut self.text)
+      .trimmed()
+      .not_empty()
+      .length(5, 500);
+    validator.string("phone", &mut self.phone).trimmed().not_empty().phone();
+    validator.min("district_id", self.district_id, 1);
+    validator.min("neighborhood_id", self.neighborhood_id, 1);
+  }
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -22,125 +61,124 @@ pub struct CreatedResponse {
 }
 
 pub async fn create(
-  request: HttpRequest,
+  user: AuthUser,
   app_data: web::Data<crate::AppState>,
-  body: web::Json<CreateAddressBody>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id) => {
-        let address = Address::new(
-          ObjectId::with_string(user_id).expect("Invalid ObjectId string"),
-          &body.name,
-          &body.surname,
-          &body.title,
-          &body.text,
-          &body.phone,
-          body.district_id,
-          body.neighborhood_id,
-        );
-        let create_address_result =
-          web::block(move || app_data.service_container.address.create(&address)).await;
-        match create_address_result {
-          Ok(response) => {
-            let response = CreatedResponse {
-              id: response.inserted_id,
-              message: String::from("Address has been successfully created"),
-            };
-            HttpResponse::Created().json(response)
-          }
-          Err(e) => {
-            println!("Error while creating address, {:?}", e);
-            HttpResponse::InternalServerError().finish()
-          }
-        }
-      }
-      Err(_e) => {
-        println!(
-          "Error while stringifying user_id header, {:?}",
-          user_id_header
-        );
-        HttpResponse::BadRequest().finish()
-      }
-    },
-    None => HttpResponse::Unauthorized().finish(),
-  }
+  body: Valid<CreateAddressBody>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let address = Address::new(
+    user_id,
+    &body.name,
+    &body.surname,
+    &body.title,
+    &body.text,
+    &body.phone,
+    body.district_id,
+    body.neighborhood_id,
+  );
+  let body = body.into_inner();
+  let id = web::block(move || {
+    validate_location(
+      &app_data.service_container.location,
+      body.district_id,
+      body.neighborhood_id,
+    )?;
+    create_address(app_data.service_container.address.clone(), address, body.is_default)
+  })
+  .await?;
+  Ok(HttpResponse::Created().json(CreatedResponse {
+    id,
+    message: String::from("Address has been successfully created"),
+  }))
 }
 
-pub async fn get_all(request: HttpRequest, app_data: web::Data<crate::AppState>) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id_str) => {
-        let user_id = String::from(user_id_str);
-        let create_address_result =
-          web::block(move || app_data.service_container.address.get_all(&user_id)).await;
-        match create_address_result {
-          Ok(response) => HttpResponse::Ok().json(response),
-          Err(e) => {
-            println!("Error while creating address, {:?}", e);
-            HttpResponse::InternalServerError().finish()
-          }
-        }
-      }
-      Err(_e) => {
-        println!(
-          "Error while stringifying user_id header, {:?}",
-          user_id_header
-        );
-        HttpResponse::BadRequest().finish()
-      }
-    },
-    None => HttpResponse::Unauthorized().finish(),
-  }
+pub async fn get_all(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let addresses = web::block(move || {
+    get_addresses(
+      &app_data.service_container.address,
+      &app_data.service_container.location,
+      &user_id,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(addresses))
 }
 
 #[derive(Deserialize)]
 pub struct UpdatePath {
-  address_id: String,
+  address_id: ObjectIdParam,
 }
 
 pub async fn update(
-  request: HttpRequest,
+  user: AuthUser,
   app_data: web::Data<crate::AppState>,
-  body: web::Json<CreateAddressBody>,
+  body: Valid<CreateAddressBody>,
   path: web::Path<UpdatePath>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id) => {
-        let address = Address::new(
-          ObjectId::with_string(user_id).expect("Invalid ObjectId string"),
-          &body.name,
-          &body.surname,
-          &body.title,
-          &body.text,
-          &body.phone,
-          body.district_id,
-          body.neighborhood_id,
-        );
-        let address_result = web::block(move || {
-          app_data
-            .service_container
-            .address
-            .update(&address, &path.address_id)
-        })
-        .await;
-        match address_result {
-          Ok(_response) => HttpResponse::Ok().finish(),
-          Err(e) => {
-            println!("Error while creating address, {:?}", e);
-            HttpResponse::InternalServerError().finish()
-          }
-        }
-      }
-      Err(_e) => {
-        println!(
-          "Error while stringifying user_id header, {:?}",
-          user_id_header
-        );
-        HttpResponse::BadRequest().finish()
-      }
-    },
-    None => HttpResponse::Unauthorized().finish(),
-  }
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let address = Address::new(
+    user_id,
+    &body.name,
+    &body.surname,
+    &body.title,
+    &body.text,
+    &body.phone,
+    body.district_id,
+    body.neighborhood_id,
+  );
+  let body = body.into_inner();
+  web::block(move || {
+    validate_location(
+      &app_data.service_container.location,
+      body.district_id,
+      body.neighborhood_id,
+    )?;
+    update_address(
+      app_data.service_container.address.clone(),
+      address,
+      path.address_id.clone().into_inner(),
+      body.is_default,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn set_default(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<UpdatePath>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  web::block(move || {
+    make_default(
+      app_data.service_container.address.clone(),
+      path.address_id.clone().into_inner(),
+      user_id,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().finish())
+}
+
+// Soft delete: orders keep their embedded copy of the address.
+pub async fn delete(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<UpdatePath>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  web::block(move || {
+    delete_address(
+      app_data.service_container.address.clone(),
+      path.address_id.clone().into_inner(),
+      user_id,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().finish())
 }
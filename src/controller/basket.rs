@@ -1,11 +1,26 @@
-use crate::action::basket::{add_to_basket, decrement_product_count};
+use crate::action::basket::{
+  add_to_basket, clear_basket, get_summary, max_item_count, remove_item, set_product_count,
+};
+use crate::action::coupon::{apply_coupon, remove_coupon};
+use crate::error::ApiError;
+use crate::middleware::user::OptionalAuthUser;
+use crate::middleware::validation::Valid;
+use crate::model::user::AuthUser;
+use crate::model::object_id::ObjectIdParam;
+use crate::model::validation::Validator;
+use crate::traits::validate::Validate;
 use crate::{action::user::create_anon_with_basket, traits::service::Finder};
-use actix_web::{http, web, HttpRequest, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Debug)]
 pub struct Body {
-  pub listing_id: String,
+  pub listing_id: ObjectIdParam,
+}
+
+// `listing_id` is already checked while deserializing.
+impl Validate for Body {
+  fn validate(&mut self, _validator: &mut Validator) {}
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -16,187 +31,247 @@ pub struct Response {
 
 pub async fn add(
   request: HttpRequest,
+  user: OptionalAuthUser,
   app_data: web::Data<crate::AppState>,
-  body: web::Json<Body>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => {
+  body: Valid<Body>,
+) -> Result<HttpResponse, ApiError> {
+  // An anonymous request logs a new guest in; replaying it would hand that guest's session to
+  // anyone repeating the key, so only requests with a principal are tracked.
+  let scope = match &user.0 {
+    Some(user) => user.id.to_string(),
+    None => return add_response(user, app_data, body.into_inner()).await,
+  };
+  let fingerprint = format!("POST /basket {:?}", body.0);
+  super::idempotent(&request, &app_data.clone(), scope, fingerprint, || {
+    add_response(user, app_data, body.into_inner())
+  })
+  .await
+}
+
+async fn add_response(
+  user: OptionalAuthUser,
+  app_data: web::Data<crate::AppState>,
+  body: Body,
+) -> Result<HttpResponse, ApiError> {
+  match user.0 {
+    Some(user) => {
       // user
-      match user_id_header.to_str() {
-        Ok(user_id_str) => {
-          let user_id = String::from(user_id_str);
-          let result =
-            web::block(
-              move || match app_data.service_container.listing.find(&body.listing_id) {
-                Ok(listing_option) => match listing_option {
-                  Some(listing) => match listing.get_object_id("product_id") {
-                    Ok(product_id) => match listing.get_object_id("seller_id") {
-                      Ok(seller_id) => add_to_basket(
-                        app_data.service_container.basket.clone(),
-                        user_id.clone(),
-                        product_id.to_string(),
-                        seller_id.to_string(),
-                        body.listing_id.clone(),
-                      ),
-                      Err(_e) => Err("Error while getting seller_id".to_string()),
-                    },
-                    Err(_e) => Err("Error while getting product_id".to_string()),
-                  },
-                  None => Err("Listing not exists".to_string()),
-                },
-                Err(_e) => Err("Error while getting listing".to_string()),
-              },
-            )
-            .await;
-
-          match result {
-            Ok(_response) => HttpResponse::Ok().finish(),
-            Err(_e) => HttpResponse::InternalServerError().finish(),
-          }
-        }
-        Err(e) => {
-          println!(
-            "Error while getting string of user_id header value, {:?}",
-            e
-          );
-          HttpResponse::InternalServerError().finish()
-        }
-      }
+      web::block(move || {
+        let listing = app_data
+          .service_container
+          .listing
+          .find(&body.listing_id)?
+          .ok_or(ApiError::NotFound("listing_not_found"))?;
+        add_to_basket(
+          app_data.service_container.basket.clone(),
+          app_data.service_container.listing.clone(),
+          app_data.service_container.reservation.clone(),
+          user.id,
+          listing.get_object_id("product_id")?.clone(),
+          listing.get_object_id("seller_id")?.clone(),
+          body.listing_id.clone().into_inner(),
+        )
+      })
+      .await?;
+      Ok(HttpResponse::Ok().finish())
     }
     None => {
       // anon
-      let result =
-        web::block(
-          move || match app_data.service_container.listing.find(&body.listing_id) {
-            Ok(listing_option) => match listing_option {
-              Some(listing) => match listing.get_object_id("product_id") {
-                Ok(product_id) => match listing.get_object_id("seller_id") {
-                  Ok(seller_id) => create_anon_with_basket(
-                    app_data.service_container.user.clone(),
-                    app_data.service_container.basket.clone(),
-                    product_id.to_string(),
-                    seller_id.to_string(),
-                    body.listing_id.clone(),
-                  ),
-                  Err(_e) => Err("Error while getting seller_id".to_string()),
-                },
-                Err(_e) => Err("Error while getting product_id".to_string()),
-              },
-              None => Err("Listing not exists".to_string()),
-            },
-            Err(_e) => Err("Error while getting listing".to_string()),
-          },
+      let tokens = web::block(move || {
+        let listing = app_data
+          .service_container
+          .listing
+          .find(&body.listing_id)?
+          .ok_or(ApiError::NotFound("listing_not_found"))?;
+        create_anon_with_basket(
+          app_data.service_container.user.clone(),
+          app_data.service_container.basket.clone(),
+          app_data.service_container.listing.clone(),
+          app_data.service_container.reservation.clone(),
+          app_data.service_container.token.clone(),
+          listing.get_object_id("product_id")?.clone(),
+          listing.get_object_id("seller_id")?.clone(),
+          body.listing_id.clone().into_inner(),
         )
-        .await;
-
-      match result {
-        Ok(cookie) => HttpResponse::Ok()
-          .header(
-            "Set-Cookie",
-            http::header::HeaderValue::from_str(&cookie).unwrap(),
-          )
-          .finish(),
-        Err(e) => {
-          println!("{:?}", e);
-          HttpResponse::InternalServerError().finish()
-        }
-      }
+      })
+      .await?;
+      Ok(super::tokens_response(tokens))
     }
   }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct SummaryQuery {
+  // shipping is priced for the user's default address when omitted
+  pub address_id: Option<ObjectIdParam>,
+}
+
 pub async fn get_active(
-  request: HttpRequest,
+  user: AuthUser,
   app_data: web::Data<crate::AppState>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id_str) => {
-        let user_id = String::from(user_id_str);
-        let active_basket_result =
-          web::block(move || app_data.service_container.basket.get_active(&user_id)).await;
-        match active_basket_result {
-          Ok(active_basket_option) => match active_basket_option {
-            Some(document) => HttpResponse::Ok().json(document),
-            None => HttpResponse::NotFound().body("active basket not exists"),
-          },
-          Err(e) => {
-            println!("Error while creating anon user: {:?}", e);
-            HttpResponse::new(http::StatusCode::INTERNAL_SERVER_ERROR)
-          }
-        }
-      }
-      Err(e) => {
-        println!("Error while stringifying user_id header, {:?}", e);
-        HttpResponse::Unauthorized().body("user id is not type of string")
-      }
-    },
-    None => HttpResponse::Unauthorized().finish(),
-  }
+  query: web::Query<SummaryQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let summary = web::block(move || {
+    get_summary(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.coupon,
+      &app_data.service_container.order,
+      &app_data.service_container.address,
+      &app_data.service_container.delivery_zone,
+      &user_id,
+      query.address_id.as_deref(),
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(summary))
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Debug)]
 pub struct UpdateBody {
-  pub listing_id: String,
+  pub listing_id: ObjectIdParam,
   pub count: i32,
 }
 
+impl Validate for UpdateBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validator.range("count", self.count, 0, max_item_count());
+  }
+}
+
 pub async fn update(
-  request: HttpRequest,
-  body: web::Json<UpdateBody>,
+  user: AuthUser,
+  body: Valid<UpdateBody>,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let summary = web::block(move || {
+    set_product_count(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.reservation,
+      &body.listing_id,
+      &user_id,
+      body.count,
+    )?;
+    get_summary(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.coupon,
+      &app_data.service_container.order,
+      &app_data.service_container.address,
+      &app_data.service_container.delivery_zone,
+      &user_id,
+      None,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(summary))
+}
+
+#[derive(Deserialize)]
+pub struct ItemPath {
+  pub listing_id: ObjectIdParam,
+}
+
+pub async fn remove(
+  user: AuthUser,
+  path: web::Path<ItemPath>,
   app_data: web::Data<crate::AppState>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id_str) => {
-        let user_id = String::from(user_id_str);
-        if body.count > 0 {
-          let update_product_count_response = web::block(move || {
-            match app_data.service_container.basket.update_product_count(
-              &body.listing_id,
-              &user_id,
-              1,
-            ) {
-              Ok(response) => Ok(response),
-              Err(_e) => Err("Error while updating product count".to_string()),
-            }
-          })
-          .await;
-
-          match update_product_count_response {
-            Ok(option) => match option {
-              Some(document) => HttpResponse::Ok().json(document),
-              None => HttpResponse::NotFound().body("product does not exist in basket"),
-            },
-            Err(e) => {
-              println!("Error while updating product count, {:?}", e);
-              HttpResponse::InternalServerError().finish()
-            }
-          }
-        } else {
-          let decrement_product_count_result = web::block(move || {
-            match decrement_product_count(
-              &app_data.service_container.basket,
-              &body.listing_id,
-              &user_id,
-            ) {
-              Ok(response) => Ok(response),
-              Err(_e) => Err("Error while decrementing product count".to_string()),
-            }
-          })
-          .await;
-
-          match decrement_product_count_result {
-            Ok(_response) => HttpResponse::Ok().finish(),
-            Err(_e) => HttpResponse::InternalServerError().finish(),
-          }
-        }
-      }
-      Err(e) => {
-        println!("Error while stringifying user_id header, {:?}", e);
-        HttpResponse::NotFound().body("user id is not type of string")
-      }
-    },
-    None => HttpResponse::NotFound().body("user does not exist"),
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let summary = web::block(move || {
+    remove_item(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.reservation,
+      &path.listing_id,
+      &user_id,
+    )?;
+    get_summary(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.coupon,
+      &app_data.service_container.order,
+      &app_data.service_container.address,
+      &app_data.service_container.delivery_zone,
+      &user_id,
+      None,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(summary))
+}
+
+pub async fn clear(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  web::block(move || {
+    clear_basket(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.reservation,
+      &user_id,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CouponBody {
+  pub code: String,
+}
+
+impl Validate for CouponBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validator.string("code", &mut self.code).trimmed().not_empty().length(1, 32);
   }
 }
+
+pub async fn add_coupon(
+  user: AuthUser,
+  body: Valid<CouponBody>,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let summary = web::block(move || {
+    apply_coupon(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.coupon,
+      &app_data.service_container.order,
+      &app_data.service_container.address,
+      &app_data.service_container.delivery_zone,
+      &user_id,
+      &body.code,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(summary))
+}
+
+pub async fn delete_coupon(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let summary = web::block(move || {
+    remove_coupon(&app_data.service_container.basket, &user_id)?;
+    get_summary(
+      &app_data.service_container.basket,
+      &app_data.service_container.listing,
+      &app_data.service_container.coupon,
+      &app_data.service_container.order,
+      &app_data.service_container.address,
+      &app_data.service_container.delivery_zone,
+      &user_id,
+      None,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(summary))
+}
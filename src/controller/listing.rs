@@ -1,38 +1,228 @@
-use actix_web::{web, HttpResponse, Responder};
-use serde::Deserialize;
-
-pub async fn get(app_data: web::Data<crate::AppState>) -> impl Responder {
-  let result = web::block(move || app_data.service_container.listing.get_for_homepage()).await;
-  match result {
-    Ok(result) => HttpResponse::Ok().json(result),
-    Err(e) => {
-      println!("Error while getting listings, {:?}", e);
-      HttpResponse::InternalServerError().finish()
-    }
-  }
+use crate::action::listing::{create_listing, delete_listing, update_listing};
+use crate::error::ApiError;
+use crate::middleware::validation::Valid;
+use crate::model::listing::{ListingPatch, ListingQuery, Product, ProductPatch};
+use crate::model::object_id::ObjectIdParam;
+use crate::model::user::AuthUser;
+use crate::model::validation::Validator;
+use crate::traits::validate::Validate;
+use actix_web::{web, HttpResponse};
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+pub async fn get(
+  app_data: web::Data<crate::AppState>,
+  query: web::Query<ListingQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let result =
+    web::block(move || app_data.service_container.listing.get_for_homepage(&query)).await?;
+  Ok(HttpResponse::Ok().json(result))
 }
 
 #[derive(Deserialize)]
 pub struct GetForSellerPath {
-  seller: String,
+  seller: ObjectIdParam,
 }
 
 pub async fn get_for_seller(
   app_data: web::Data<crate::AppState>,
   path: web::Path<GetForSellerPath>,
-) -> impl Responder {
+  query: web::Query<ListingQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let result = web::block(move || {
+    app_data
+      .service_container
+      .listing
+      .get_for_seller(&path.seller, &query)
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize)]
+pub struct FindPath {
+  listing_id: ObjectIdParam,
+}
+
+pub async fn find(
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<FindPath>,
+) -> Result<HttpResponse, ApiError> {
+  let listing = web::block(move || {
+    app_data
+      .service_container
+      .listing
+      .find_with_details(&path.listing_id)
+  })
+  .await?;
+  match listing {
+    Some(listing) => Ok(HttpResponse::Ok().json(listing)),
+    None => Err(ApiError::NotFound("listing_not_found")),
+  }
+}
+
+fn seller_of(user: &AuthUser) -> Result<ObjectId, ApiError> {
+  user
+    .seller_id
+    .clone()
+    .ok_or(ApiError::Forbidden("seller_not_assigned"))
+}
+
+pub async fn get_own(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let seller_id = seller_of(&user)?;
   let result = web::block(move || {
     app_data
       .service_container
       .listing
-      .get_for_seller(&path.seller)
+      .get_all_for_owner(&seller_id)
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateListingBody {
+  #[serde(flatten)]
+  listing: ListingPatch,
+  product: Product,
+}
+
+// Checks the fields sent; on create `header` and `type` are required. Rules that span several
+// fields, such as old price above price, are checked once the patch is applied.
+fn validate_listing(listing: &mut ListingPatch, validator: &mut Validator, create: bool) {
+  if create {
+    listing.header.get_or_insert_with(String::new);
+    listing.kind.get_or_insert_with(String::new);
+  }
+  if let Some(header) = listing.header.as_mut() {
+    validator.string("header", header).trimmed().not_empty().length(1, 120);
+  }
+  if let Some(text) = listing.text.as_mut() {
+    validator.string("text", text).length(0, 2000);
+  }
+  if let Some(kind) = listing.kind.as_mut() {
+    validator.string("type", kind).trimmed().not_empty().length(1, 32);
+  }
+  if let Some(priority) = listing.priority {
+    validator.min("priority", priority, 0);
+  }
+  if let Some(stock) = listing.stock {
+    validator.min("stock", stock, 0);
+  }
+}
+
+impl Validate for CreateListingBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validate_listing(&mut self.listing, validator, true);
+    validator
+      .string("product.name", &mut self.product.name)
+      .trimmed()
+      .not_empty()
+      .length(1, 120);
+    validator.min("product.price", self.product.price, 0.01);
+    if let Some(old_price) = self.product.old_price {
+      validator.min("product.old_price", old_price, 0.01);
+    }
+  }
+}
+
+#[derive(Deserialize, Debug, Serialize)]
+pub struct CreatedResponse {
+  id: bson::Bson,
+  message: String,
+}
+
+pub async fn create(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  body: Valid<CreateListingBody>,
+) -> Result<HttpResponse, ApiError> {
+  let seller_id = seller_of(&user)?;
+  let body = body.into_inner();
+  let id = web::block(move || {
+    create_listing(
+      app_data.service_container.listing.clone(),
+      app_data.service_container.product.clone(),
+      seller_id,
+      body.listing,
+      body.product,
+    )
   })
-  .await;
-  match result {
-    Ok(result) => HttpResponse::Ok().json(result),
-    Err(e) => {
-      println!("Error while getting listings, {:?}", e);
-      HttpResponse::InternalServerError().finish()
+  .await?;
+  Ok(HttpResponse::Created().json(CreatedResponse {
+    id,
+    message: String::from("Listing has been successfully created"),
+  }))
+}
+
+#[derive(Deserialize)]
+pub struct ListingPath {
+  id: ObjectIdParam,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateListingBody {
+  #[serde(flatten)]
+  listing: ListingPatch,
+  product: Option<ProductPatch>,
+}
+
+impl Validate for UpdateListingBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validate_listing(&mut self.listing, validator, false);
+    if let Some(product) = self.product.as_mut() {
+      if let Some(name) = product.name.as_mut() {
+        validator.string("product.name", name).trimmed().not_empty().length(1, 120);
+      }
+      if let Some(price) = product.price {
+        validator.min("product.price", price, 0.01);
+      }
+      if let Some(Some(old_price)) = product.old_price {
+        validator.min("product.old_price", old_price, 0.01);
+      }
     }
   }
 }
+
+pub async fn update(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<ListingPath>,
+  body: Valid<UpdateListingBody>,
+) -> Result<HttpResponse, ApiError> {
+  let seller_id = seller_of(&user)?;
+  let body = body.into_inner();
+  web::block(move || {
+    update_listing(
+      app_data.service_container.listing.clone(),
+      app_data.service_container.product.clone(),
+      seller_id,
+      path.id.clone().into_inner(),
+      body.listing,
+      body.product,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn delete(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<ListingPath>,
+) -> Result<HttpResponse, ApiError> {
+  let seller_id = seller_of(&user)?;
+  web::block(move || {
+    delete_listing(
+      app_data.service_container.listing.clone(),
+      app_data.service_container.product.clone(),
+      seller_id,
+      path.id.clone().into_inner(),
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().finish())
+}
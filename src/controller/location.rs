@@ -0,0 +1,25 @@
+use crate::error::ApiError;
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+
+pub async fn get_districts(app_data: web::Data<crate::AppState>) -> Result<HttpResponse, ApiError> {
+  let districts = web::block(move || app_data.service_container.location.get_districts()).await?;
+  Ok(HttpResponse::Ok().json(districts))
+}
+
+#[derive(Deserialize)]
+pub struct DistrictPath {
+  id: i32,
+}
+
+pub async fn get_neighborhoods(
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<DistrictPath>,
+) -> Result<HttpResponse, ApiError> {
+  let district =
+    web::block(move || app_data.service_container.location.find_district(path.id)).await?;
+  match district {
+    Some(district) => Ok(HttpResponse::Ok().json(district.neighborhoods)),
+    None => Err(ApiError::NotFound("district_not_found")),
+  }
+}
@@ -4,3 +4,135 @@ pub mod basket;
 pub mod address;
 pub mod order;
 pub mod seller;
+pub mod product;
+pub mod search;
+pub mod location;
+
+use crate::error::ApiError;
+use crate::model::idempotency::{Claim, StoredResponse};
+use crate::model::token::TokenPair;
+use actix_web::dev::{Body, ResponseBody};
+use actix_web::http::{header, Cookie, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse};
+use bson::UtcDateTime;
+use futures::Future;
+
+fn access_token_cookie(value: String) -> Cookie<'static> {
+  Cookie::build("access_token", value)
+    .path("/")
+    .http_only(true)
+    .finish()
+}
+
+fn refresh_token_cookie(value: String) -> Cookie<'static> {
+  Cookie::build("refresh_token", value)
+    .path("/users")
+    .http_only(true)
+    .finish()
+}
+
+fn tokens_response(tokens: TokenPair) -> HttpResponse {
+  HttpResponse::Ok()
+    .cookie(access_token_cookie(tokens.access_token))
+    .cookie(refresh_token_cookie(tokens.refresh_token))
+    .finish()
+}
+
+fn clear_tokens_response() -> HttpResponse {
+  HttpResponse::Ok()
+    .del_cookie(&access_token_cookie(String::new()))
+    .del_cookie(&refresh_token_cookie(String::new()))
+    .finish()
+}
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+fn stored_response(response: &HttpResponse) -> StoredResponse {
+  let body = match response.body() {
+    ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => {
+      String::from_utf8_lossy(bytes).to_string()
+    }
+    _ => String::new(),
+  };
+  StoredResponse {
+    status: response.status().as_u16() as i32,
+    content_type: response
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .map(String::from),
+    body,
+  }
+}
+
+fn replay_response(stored: StoredResponse) -> HttpResponse {
+  let status = StatusCode::from_u16(stored.status as u16).unwrap_or(StatusCode::OK);
+  let mut builder = HttpResponse::build(status);
+  builder.header("Idempotent-Replayed", "true");
+  if let Some(content_type) = stored.content_type {
+    builder.content_type(content_type);
+  }
+  builder.body(stored.body)
+}
+
+// Runs `handler` at most once per Idempotency-Key and scope, replaying the stored
+// response for repeated requests. Requests without the header are not tracked.
+async fn idempotent<F, Fut>(
+  request: &HttpRequest,
+  app_data: &web::Data<crate::AppState>,
+  scope: String,
+  fingerprint: String,
+  handler: F,
+) -> Result<HttpResponse, ApiError>
+where
+  F: FnOnce() -> Fut,
+  Fut: Future<Output = Result<HttpResponse, ApiError>>,
+{
+  let key = match request.headers().get(IDEMPOTENCY_KEY_HEADER) {
+    Some(key) => key
+      .to_str()
+      .map(String::from)
+      .map_err(|_e| ApiError::Validation(String::from("Idempotency-Key header is not valid")))?,
+    None => return handler().await,
+  };
+
+  let idempotency = app_data.service_container.idempotency.clone();
+  let expires_at = UtcDateTime(
+    chrono::Utc::now()
+      + chrono::Duration::seconds(
+        dotenv!("IDEMPOTENCY_TTL_SECONDS")
+          .parse::<i64>()
+          .expect("IDEMPOTENCY_TTL_SECONDS is not a number"),
+      ),
+  );
+  let locked_until = UtcDateTime(
+    chrono::Utc::now()
+      + chrono::Duration::seconds(
+        dotenv!("IDEMPOTENCY_LEASE_SECONDS")
+          .parse::<i64>()
+          .expect("IDEMPOTENCY_LEASE_SECONDS is not a number"),
+      ),
+  );
+  let claim = {
+    let idempotency = idempotency.clone();
+    let (key, scope) = (key.clone(), scope.clone());
+    web::block(move || idempotency.claim(&key, &scope, &fingerprint, locked_until, expires_at))
+      .await?
+  };
+
+  match claim {
+    Claim::Replay(stored) => Ok(replay_response(stored)),
+    Claim::InProgress => Err(ApiError::Conflict("request_in_progress")),
+    Claim::New => match handler().await {
+      Ok(response) => {
+        let stored = stored_response(&response);
+        web::block(move || idempotency.complete(&key, &scope, &stored)).await?;
+        Ok(response)
+      }
+      Err(e) => {
+        web::block(move || idempotency.release(&key, &scope)).await?;
+        Err(e)
+      }
+    },
+  }
+}
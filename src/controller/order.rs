@@ -1,122 +1,165 @@
-use crate::action::order::{create_order, CreateOrderResponse};
-use crate::traits::service::Getter;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use crate::action::order::{create_order, get_for_seller, update_status, update_sub_order_status};
+use crate::error::ApiError;
+use crate::middleware::validation::Valid;
+use crate::model::object_id::ObjectIdParam;
+use crate::model::order::{OrderQuery, Status};
+use crate::model::user::AuthUser;
+use crate::model::validation::Validator;
+use crate::traits::validate::Validate;
+use actix_web::{web, HttpRequest, HttpResponse};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CreateOrderBody {
-  address_id: String,
+  // the user's default address is used when omitted
+  address_id: Option<ObjectIdParam>,
+  expected_total: f64,
+}
+
+impl Validate for CreateOrderBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validator.min("expected_total", self.expected_total, 0.0);
+  }
 }
 
 pub async fn create(
   request: HttpRequest,
+  user: AuthUser,
   app_data: web::Data<crate::AppState>,
-  body: web::Json<CreateOrderBody>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => {
-      // user
-      match user_id_header.to_str() {
-        Ok(user_id_str) => {
-          let user_id = String::from(user_id_str);
-          let result = web::block(move || {
-            create_order(
-              app_data.service_container.order.clone(),
-              app_data.service_container.basket.clone(),
-              app_data.service_container.address.clone(),
-              user_id.clone(),
-              body.address_id.clone(),
-            )
-          })
-          .await;
+  body: Valid<CreateOrderBody>,
+) -> Result<HttpResponse, ApiError> {
+  let scope = user.id.to_string();
+  let fingerprint = format!("POST /orders {:?}", body.0);
+  super::idempotent(&request, &app_data.clone(), scope, fingerprint, || {
+    create_response(user, app_data, body.into_inner())
+  })
+  .await
+}
 
-          match result {
-            Ok(response) => match response {
-              CreateOrderResponse::OrderCreated(id) => HttpResponse::Ok().json(id),
-              CreateOrderResponse::ActiveBasketNotFound => {
-                HttpResponse::BadRequest().body("Active Basket Not Found")
-              }
-              CreateOrderResponse::AddressNotFound => {
-                HttpResponse::BadRequest().body("Address Not Found")
-              }
-              CreateOrderResponse::BasketToDeleteNotFound => {
-                HttpResponse::BadRequest().body("Basket To Delete Not Found")
-              }
-            },
-            Err(_e) => HttpResponse::InternalServerError().finish(),
-          }
-        }
-        Err(e) => {
-          println!(
-            "Error while getting string of user_id header value, {:?}",
-            e
-          );
-          HttpResponse::InternalServerError().finish()
-        }
-      }
-    }
-    None => HttpResponse::Unauthorized().finish(),
-  }
+async fn create_response(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  body: CreateOrderBody,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let id = web::block(move || {
+    create_order(
+      app_data.service_container.order.clone(),
+      app_data.service_container.basket.clone(),
+      app_data.service_container.address.clone(),
+      app_data.service_container.listing.clone(),
+      app_data.service_container.reservation.clone(),
+      app_data.service_container.coupon.clone(),
+      app_data.service_container.delivery_zone.clone(),
+      app_data.service_container.session.clone(),
+      user_id,
+      body.address_id.map(ObjectIdParam::into_inner),
+      body.expected_total,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(id))
 }
 
-pub async fn get_all(request: HttpRequest, app_data: web::Data<crate::AppState>) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id_str) => {
-        let user_id = String::from(user_id_str);
-        let orders = web::block(move || app_data.service_container.order.get_all(&user_id)).await;
-        match orders {
-          Ok(response) => HttpResponse::Ok().json(response),
-          Err(e) => {
-            println!("Error whil getting orders, {:?}", e);
-            HttpResponse::InternalServerError().finish()
-          }
-        }
-      }
-      Err(_e) => {
-        println!(
-          "Error while stringifying user_id header, {:?}",
-          user_id_header
-        );
-        HttpResponse::BadRequest().finish()
-      }
-    },
-    None => HttpResponse::Unauthorized().finish(),
-  }
+pub async fn get_all(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+  query: web::Query<OrderQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let orders =
+    web::block(move || app_data.service_container.order.get_page(&user_id, &query)).await?;
+  Ok(HttpResponse::Ok().json(orders))
 }
 
 #[derive(Deserialize)]
 pub struct FindPath {
-  pub id: String,
+  pub id: ObjectIdParam,
 }
 
 pub async fn find(
-  request: HttpRequest,
+  user: AuthUser,
   path: web::Path<FindPath>,
   app_data: web::Data<crate::AppState>,
-) -> impl Responder {
-  match request.headers().get("user_id") {
-    Some(user_id_header) => match user_id_header.to_str() {
-      Ok(user_id_str) => {
-        let user_id = String::from(user_id_str);
-        let order =
-          web::block(move || app_data.service_container.order.find(&path.id, &user_id)).await;
-        match order {
-          Ok(response) => HttpResponse::Ok().json(response),
-          Err(e) => {
-            println!("Error while finding order, {:?}", e);
-            HttpResponse::InternalServerError().finish()
-          }
-        }
-      }
-      Err(_e) => {
-        println!(
-          "Error while stringifying user_id header, {:?}",
-          user_id_header
-        );
-        HttpResponse::BadRequest().finish()
-      }
-    },
-    None => HttpResponse::Unauthorized().finish(),
+) -> Result<HttpResponse, ApiError> {
+  let user_id = user.id;
+  let order =
+    web::block(move || app_data.service_container.order.find(&path.id, &user_id)).await?;
+  match order {
+    Some(order) => Ok(HttpResponse::Ok().json(order)),
+    None => Err(ApiError::NotFound("order_not_found")),
   }
 }
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateStatusBody {
+  status: Status,
+}
+
+pub async fn advance_status(
+  user: AuthUser,
+  path: web::Path<FindPath>,
+  body: web::Json<UpdateStatusBody>,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let order = web::block(move || {
+    update_status(
+      app_data.service_container.order.clone(),
+      app_data.service_container.listing.clone(),
+      app_data.service_container.coupon.clone(),
+      user,
+      path.id.clone().into_inner(),
+      body.status,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(order))
+}
+
+pub async fn cancel(
+  user: AuthUser,
+  path: web::Path<FindPath>,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let order = web::block(move || {
+    update_status(
+      app_data.service_container.order.clone(),
+      app_data.service_container.listing.clone(),
+      app_data.service_container.coupon.clone(),
+      user,
+      path.id.clone().into_inner(),
+      Status::Cancelled,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(order))
+}
+
+pub async fn get_all_for_seller(
+  user: AuthUser,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let orders =
+    web::block(move || get_for_seller(app_data.service_container.order.clone(), user)).await?;
+  Ok(HttpResponse::Ok().json(orders))
+}
+
+pub async fn advance_sub_order_status(
+  user: AuthUser,
+  path: web::Path<FindPath>,
+  body: web::Json<UpdateStatusBody>,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let order = web::block(move || {
+    update_sub_order_status(
+      app_data.service_container.order.clone(),
+      app_data.service_container.listing.clone(),
+      app_data.service_container.coupon.clone(),
+      user,
+      path.id.clone().into_inner(),
+      body.status,
+    )
+  })
+  .await?;
+  Ok(HttpResponse::Ok().json(order))
+}
@@ -0,0 +1,37 @@
+use crate::error::ApiError;
+use crate::model::object_id::ObjectIdParam;
+use crate::traits::service::Finder;
+use actix_web::{web, HttpResponse};
+use bson::Bson;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct FindPath {
+  id: ObjectIdParam,
+}
+
+// A product is only visible through its visible listings.
+pub async fn find(
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<FindPath>,
+) -> Result<HttpResponse, ApiError> {
+  let product = web::block(move || -> Result<_, ApiError> {
+    let listings = app_data.service_container.listing.get_for_product(&path.id)?;
+    if listings.is_empty() {
+      return Ok(None);
+    }
+    let product = app_data.service_container.product.find(&path.id)?;
+    Ok(product.map(|mut product| {
+      product.insert(
+        "listings",
+        Bson::Array(listings.into_iter().map(Bson::Document).collect()),
+      );
+      product
+    }))
+  })
+  .await?;
+  match product {
+    Some(product) => Ok(HttpResponse::Ok().json(product)),
+    None => Err(ApiError::NotFound("product_not_found")),
+  }
+}
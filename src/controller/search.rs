@@ -0,0 +1,11 @@
+use crate::error::ApiError;
+use crate::model::search::SearchQuery;
+use actix_web::{web, HttpResponse};
+
+pub async fn search(
+  app_data: web::Data<crate::AppState>,
+  query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+  let result = web::block(move || app_data.service_container.search.search(&query)).await?;
+  Ok(HttpResponse::Ok().json(result))
+}
@@ -1,4 +1,5 @@
-use actix_web::{web, HttpResponse, Responder};
+use crate::error::ApiError;
+use actix_web::{web, HttpResponse};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -6,13 +7,13 @@ pub struct GetPath {
   name: String,
 }
 
-pub async fn get(app_data: web::Data<crate::AppState>, path: web::Path<GetPath>) -> impl Responder {
-  let result = web::block(move || app_data.service_container.seller.get(&path.name)).await;
+pub async fn get(
+  app_data: web::Data<crate::AppState>,
+  path: web::Path<GetPath>,
+) -> Result<HttpResponse, ApiError> {
+  let result = web::block(move || app_data.service_container.seller.get(&path.name)).await?;
   match result {
-    Ok(result) => HttpResponse::Ok().json(result),
-    Err(e) => {
-      println!("Error while getting seller, {:?}", e);
-      HttpResponse::InternalServerError().finish()
-    }
+    Some(seller) => Ok(HttpResponse::Ok().json(seller)),
+    None => Err(ApiError::NotFound("seller_not_found")),
   }
 }
@@ -1,7 +1,13 @@
 use crate::action;
-use crate::action::user::{LoginResult, UserCreateResult};
-use actix_web::{http, web, HttpRequest, HttpResponse, Responder};
-use serde::{Deserialize, Serialize};
+use crate::error::ApiError;
+use crate::middleware::user::OptionalAuthUser;
+use crate::middleware::validation::Valid;
+use crate::model::user::Role;
+use crate::model::validation::Validator;
+use crate::traits::validate::Validate;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use bson::oid::ObjectId;
+use serde::Deserialize;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CreateUserBody {
@@ -9,126 +15,115 @@ pub struct CreateUserBody {
   password: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct UserAlreadyRegisteredResponse {
-  pub user_already_registered: bool,
+impl Validate for CreateUserBody {
+  fn validate(&mut self, validator: &mut Validator) {
+    validator.string("phone", &mut self.phone).trimmed().not_empty().phone();
+    validator.string("password", &mut self.password).length(8, 128);
+  }
+}
+
+// A guest signing up or logging in hands over its basket and its tokens are revoked.
+fn guest_of(request: &HttpRequest, user: OptionalAuthUser) -> (Option<ObjectId>, Option<String>) {
+  match user.0 {
+    Some(user) if user.role == Role::Guest => (
+      Some(user.id),
+      request
+        .cookie("access_token")
+        .map(|cookie| cookie.value().to_string()),
+    ),
+    _ => (None, None),
+  }
 }
 
 pub async fn create(
   request: HttpRequest,
+  user: OptionalAuthUser,
   app_data: web::Data<crate::AppState>,
-  body: web::Json<CreateUserBody>,
-) -> impl Responder {
-  let user_id;
-  match request.headers().get("user_id") {
-    Some(user_id_header) => {
-      user_id = Some(user_id_header.to_str().unwrap().to_string());
-    }
-    None => user_id = None,
-  }
+  body: Valid<CreateUserBody>,
+) -> Result<HttpResponse, ApiError> {
+  let (guest_id, guest_access_token) = guest_of(&request, user);
 
-  let result = web::block(move || {
+  let tokens = web::block(move || {
     action::user::create(
       app_data.service_container.user.clone(),
+      app_data.service_container.token.clone(),
       body.phone.clone(),
       body.password.clone(),
-      user_id,
+      guest_id,
+      guest_access_token,
     )
   })
-  .await;
-
-  match result {
-    Ok(response) => match response {
-      UserCreateResult::UserCreated(cookie) => HttpResponse::Ok()
-        .header(
-          "Set-Cookie",
-          http::header::HeaderValue::from_str(&cookie).unwrap(),
-        )
-        .finish(),
-      UserCreateResult::GuestUserNotRegistered => HttpResponse::BadRequest().finish(),
-      UserCreateResult::UserAlreadyExists => HttpResponse::BadRequest().finish(),
-    },
-    Err(e) => {
-      println!("Error while creating user: {:?}", e);
-      HttpResponse::InternalServerError().finish()
-    }
-  }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct UserNotExistResponse {
-  pub user_not_exist: bool,
-}
+  .await?;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct WrongPasswordResponse {
-  pub wrong_password: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AlreadyGuestResponse {
-  already_guest: bool,
-  guest_id: String,
-  registered_id: String,
+  Ok(super::tokens_response(tokens))
 }
 
+// Login is not held to the registration rules, accounts created before them must still get in.
 #[derive(Deserialize, Debug, Clone)]
-struct UserJson {
-  _id: bson::oid::ObjectId,
-  phone: String,
+pub struct LoginBody {
+  pub phone: String,
   password: String,
 }
 
 pub async fn login(
   request: HttpRequest,
+  user: OptionalAuthUser,
   app_data: web::Data<crate::AppState>,
-  body: web::Json<CreateUserBody>,
-) -> impl Responder {
-  let user_id;
-  let user_type;
-  match request.headers().get("user_id") {
-    Some(user_id_header) => {
-      user_id = Some(user_id_header.to_str().unwrap().to_string());
-      user_type = Some(
-        request
-          .headers()
-          .get("user_type")
-          .unwrap()
-          .to_str()
-          .unwrap()
-          .to_string(),
-      );
-    }
-    None => {
-      user_id = None;
-      user_type = None;
-    }
-  }
-  let result = web::block(move || {
+  body: web::Json<LoginBody>,
+) -> Result<HttpResponse, ApiError> {
+  let (guest_id, guest_access_token) = guest_of(&request, user);
+  let tokens = web::block(move || {
     action::user::login(
       app_data.service_container.user.clone(),
+      app_data.service_container.basket.clone(),
+      app_data.service_container.listing.clone(),
+      app_data.service_container.reservation.clone(),
+      app_data.service_container.token.clone(),
       body.phone.clone(),
       body.password.clone(),
-      user_id,
-      user_type,
+      guest_id,
+      guest_access_token,
     )
   })
-  .await;
+  .await?;
 
-  match result {
-    Ok(login_result) => match login_result {
-      LoginResult::Verified(cookie) => HttpResponse::Ok()
-        .header(
-          "Set-Cookie",
-          http::header::HeaderValue::from_str(&cookie).unwrap(),
-        )
-        .finish(),
-      LoginResult::NotVerified => HttpResponse::BadRequest().finish(),
-      LoginResult::UserNotExists => HttpResponse::BadRequest().finish(),
-    },
-    Err(e) => {
-      println!("Error while creating user: {:?}", e);
-      HttpResponse::InternalServerError().finish()
-    }
-  }
+  Ok(super::tokens_response(tokens))
+}
+
+pub async fn refresh(
+  request: HttpRequest,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let refresh_token = request
+    .cookie("refresh_token")
+    .map(|cookie| cookie.value().to_string())
+    .ok_or(ApiError::Unauthorized("missing_refresh_token"))?;
+  let tokens = web::block(move || {
+    action::token::refresh(
+      &app_data.service_container.token,
+      &app_data.service_container.user,
+      &refresh_token,
+    )
+  })
+  .await?;
+
+  Ok(super::tokens_response(tokens))
+}
+
+pub async fn logout(
+  request: HttpRequest,
+  app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, ApiError> {
+  let access_token = request
+    .cookie("access_token")
+    .map(|cookie| cookie.value().to_string());
+  let refresh_token = request
+    .cookie("refresh_token")
+    .map(|cookie| cookie.value().to_string());
+  web::block(move || {
+    action::token::logout(&app_data.service_container.token, access_token, refresh_token)
+  })
+  .await?;
+
+  Ok(super::clear_tokens_response())
 }
@@ -0,0 +1,118 @@
+use crate::model::validation::FieldError;
+use actix_web::{error::BlockingError, http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ApiError {
+  NotFound(&'static str),
+  Validation(String),
+  InvalidFields(Vec<FieldError>),
+  Conflict(&'static str),
+  Unauthorized(&'static str),
+  Forbidden(&'static str),
+  Database(mongodb::error::Error),
+  Internal(String),
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+  pub code: String,
+  pub message: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub fields: Vec<FieldError>,
+}
+
+impl ApiError {
+  pub fn code(&self) -> &str {
+    match self {
+      ApiError::NotFound(code) => code,
+      ApiError::Validation(_) => "validation_failed",
+      ApiError::InvalidFields(_) => "invalid_fields",
+      ApiError::Conflict(code) => code,
+      ApiError::Unauthorized(code) => code,
+      ApiError::Forbidden(code) => code,
+      ApiError::Database(_) => "database_error",
+      ApiError::Internal(_) => "internal_error",
+    }
+  }
+}
+
+impl fmt::Display for ApiError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ApiError::NotFound(code) => write!(f, "Not found: {}", code),
+      ApiError::Validation(message) => write!(f, "{}", message),
+      ApiError::InvalidFields(errors) => write!(f, "{} fields are invalid", errors.len()),
+      ApiError::Conflict(code) => write!(f, "Conflict: {}", code),
+      ApiError::Unauthorized(code) => write!(f, "Unauthorized: {}", code),
+      ApiError::Forbidden(code) => write!(f, "Forbidden: {}", code),
+      ApiError::Database(_) => write!(f, "Database is unavailable"),
+      ApiError::Internal(_) => write!(f, "Internal server error"),
+    }
+  }
+}
+
+impl ResponseError for ApiError {
+  fn status_code(&self) -> StatusCode {
+    match self {
+      ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+      ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+      ApiError::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+      ApiError::Conflict(_) => StatusCode::CONFLICT,
+      ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+      ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+      ApiError::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+      ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  fn error_response(&self) -> HttpResponse {
+    match self {
+      ApiError::Database(e) => log::error!("Database error: {:?}", e),
+      ApiError::Internal(message) => log::error!("Internal error: {}", message),
+      _ => {}
+    }
+    HttpResponse::build(self.status_code()).json(ErrorResponse {
+      code: self.code().to_string(),
+      message: self.to_string(),
+      fields: match self {
+        ApiError::InvalidFields(errors) => errors.clone(),
+        _ => vec![],
+      },
+    })
+  }
+}
+
+impl From<mongodb::error::Error> for ApiError {
+  fn from(error: mongodb::error::Error) -> Self {
+    ApiError::Database(error)
+  }
+}
+
+impl From<bson::EncoderError> for ApiError {
+  fn from(error: bson::EncoderError) -> Self {
+    ApiError::Internal(format!("Can not encode document: {}", error))
+  }
+}
+
+impl From<bson::DecoderError> for ApiError {
+  fn from(error: bson::DecoderError) -> Self {
+    ApiError::Internal(format!("Can not decode document: {}", error))
+  }
+}
+
+impl From<bson::ordered::ValueAccessError> for ApiError {
+  fn from(error: bson::ordered::ValueAccessError) -> Self {
+    ApiError::Internal(format!("Unexpected document shape: {:?}", error))
+  }
+}
+
+impl From<BlockingError<ApiError>> for ApiError {
+  fn from(error: BlockingError<ApiError>) -> Self {
+    match error {
+      BlockingError::Error(e) => e,
+      BlockingError::Canceled => ApiError::Internal(String::from("Blocking operation is canceled")),
+    }
+  }
+}
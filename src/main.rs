@@ -3,16 +3,29 @@ use mongodb::{options::ClientOptions, Client};
 use service::address::AddressService;
 use service::basket::BasketService;
 use service::listing::ListingService;
+use service::location::LocationService;
 use service::order::OrderService;
+use service::product::ProductService;
+use service::coupon::CouponService;
+use service::delivery_zone::DeliveryZoneService;
+use service::idempotency::IdempotencyService;
+use service::reservation::ReservationService;
+use service::search::SearchService;
 use service::seller::SellerService;
+use service::session::SessionService;
+use service::token::TokenService;
 use service::user::UserService;
 use env_logger::Env;
+use model::user::Role;
 
 mod action;
 mod controller;
+mod error;
 mod middleware;
 mod model;
 mod service;
+#[cfg(test)]
+mod test_support;
 mod traits;
 
 pub struct ServiceContainer {
@@ -22,6 +35,15 @@ pub struct ServiceContainer {
   user: UserService,
   order: OrderService,
   seller: SellerService,
+  token: TokenService,
+  session: SessionService,
+  idempotency: IdempotencyService,
+  reservation: ReservationService,
+  coupon: CouponService,
+  product: ProductService,
+  search: SearchService,
+  location: LocationService,
+  delivery_zone: DeliveryZoneService,
 }
 
 impl ServiceContainer {
@@ -32,6 +54,15 @@ impl ServiceContainer {
     user: UserService,
     order: OrderService,
     seller: SellerService,
+    token: TokenService,
+    session: SessionService,
+    idempotency: IdempotencyService,
+    reservation: ReservationService,
+    coupon: CouponService,
+    product: ProductService,
+    search: SearchService,
+    location: LocationService,
+    delivery_zone: DeliveryZoneService,
   ) -> Self {
     ServiceContainer {
       address,
@@ -40,6 +71,15 @@ impl ServiceContainer {
       user,
       order,
       seller,
+      token,
+      session,
+      idempotency,
+      reservation,
+      coupon,
+      product,
+      search,
+      location,
+      delivery_zone,
     }
   }
 }
@@ -54,12 +94,60 @@ extern crate dotenv_codegen;
 #[actix_rt::main]
 async fn run(client: Client) -> std::io::Result<()> {
   let db = client.database(dotenv!("DB_NAME"));
+  let admin_db = client.database("admin");
   let listing_collection = db.collection(dotenv!("DB_LISTING_COLLECTION"));
   let user_collection = db.collection(dotenv!("DB_USER_COLLECTION"));
   let basket_collection = db.collection(dotenv!("DB_BASKET_COLLECTION"));
   let address_collection = db.collection(dotenv!("DB_ADDRESS_COLLECTION"));
   let order_collection = db.collection(dotenv!("DB_ORDER_COLLECTION"));
   let seller_collection = db.collection(dotenv!("DB_SELLER_COLLECTION"));
+  let token_collection = db.collection(dotenv!("DB_TOKEN_COLLECTION"));
+  let idempotency_collection = db.collection(dotenv!("DB_IDEMPOTENCY_COLLECTION"));
+  let reservation_collection = db.collection(dotenv!("DB_RESERVATION_COLLECTION"));
+  let coupon_collection = db.collection(dotenv!("DB_COUPON_COLLECTION"));
+  let coupon_redemption_collection = db.collection(dotenv!("DB_COUPON_REDEMPTION_COLLECTION"));
+  let product_collection = db.collection(dotenv!("DB_PRODUCT_COLLECTION"));
+  let location_collection = db.collection(dotenv!("DB_LOCATION_COLLECTION"));
+  let delivery_zone_collection = db.collection(dotenv!("DB_DELIVERY_ZONE_COLLECTION"));
+
+  let modified_count = OrderService::new(order_collection.clone())
+    .migrate_legacy_status()
+    .expect("Can not migrate legacy order statuses");
+  if modified_count > 0 {
+    log::info!("Migrated {} orders to named statuses", modified_count);
+  }
+
+  IdempotencyService::new(idempotency_collection.clone())
+    .ensure_indexes(&db)
+    .expect("Can not create idempotency indexes");
+
+  TokenService::new(token_collection.clone())
+    .ensure_indexes(&db)
+    .expect("Can not create token indexes");
+
+  AddressService::new(address_collection.clone())
+    .ensure_indexes(&db)
+    .expect("Can not create address indexes");
+
+  ReservationService::new(reservation_collection.clone())
+    .ensure_indexes(&db)
+    .expect("Can not create reservation indexes");
+
+  CouponService::new(coupon_collection.clone(), coupon_redemption_collection.clone())
+    .ensure_indexes(&db)
+    .expect("Can not create coupon indexes");
+
+  let district_count = LocationService::new(location_collection.clone())
+    .seed()
+    .expect("Can not seed locations");
+  if district_count == 0 {
+    log::warn!("No bundled locations, addresses are checked against the stored districts only");
+  } else {
+    log::info!("Seeded {} districts", district_count);
+  }
+
+  // built once so every worker shares the same search index
+  let search_service = SearchService::new(ListingService::new(listing_collection.clone()));
 
   HttpServer::new(move || {
     let service_container = ServiceContainer::new(
@@ -69,14 +157,33 @@ async fn run(client: Client) -> std::io::Result<()> {
       UserService::new(user_collection.clone()),
       OrderService::new(order_collection.clone()),
       SellerService::new(seller_collection.clone()),
+      TokenService::new(token_collection.clone()),
+      SessionService::new(db.clone(), admin_db.clone()),
+      IdempotencyService::new(idempotency_collection.clone()),
+      ReservationService::new(reservation_collection.clone()),
+      CouponService::new(coupon_collection.clone(), coupon_redemption_collection.clone()),
+      ProductService::new(product_collection.clone()),
+      search_service.clone(),
+      LocationService::new(location_collection.clone()),
+      DeliveryZoneService::new(delivery_zone_collection.clone()),
     );
     App::new()
       .wrap(Logger::default())
       .wrap(Logger::new("%a %{User-Agent}i"))
       .data(AppState { service_container })
+      .app_data(web::JsonConfig::default().error_handler(|err, _req| {
+        error::ApiError::Validation(err.to_string()).into()
+      }))
+      .app_data(web::PathConfig::default().error_handler(|err, _req| {
+        error::ApiError::Validation(err.to_string()).into()
+      }))
+      .app_data(web::QueryConfig::default().error_handler(|err, _req| {
+        error::ApiError::Validation(err.to_string()).into()
+      }))
       .service(
         web::scope("/listings")
           .route("", web::get().to(controller::listing::get))
+          .route("/id/{listing_id}", web::get().to(controller::listing::find))
           .route(
             "{seller}",
             web::get().to(controller::listing::get_for_seller),
@@ -87,8 +194,17 @@ async fn run(client: Client) -> std::io::Result<()> {
           .wrap(middleware::user::Resolve)
           .route("", web::post().to(controller::basket::add))
           .route("", web::get().to(controller::basket::get_active))
-          .route("", web::patch().to(controller::basket::update)),
+          .route("", web::patch().to(controller::basket::update))
+          .route("", web::delete().to(controller::basket::clear))
+          .route("/coupon", web::post().to(controller::basket::add_coupon))
+          .route("/coupon", web::delete().to(controller::basket::delete_coupon))
+          .route(
+            "/items/{listing_id}",
+            web::delete().to(controller::basket::remove),
+          ),
       )
+      .service(web::resource("/users/refresh").route(web::post().to(controller::user::refresh)))
+      .service(web::resource("/users/logout").route(web::post().to(controller::user::logout)))
       .service(
         web::scope("/users")
           .wrap(middleware::user::Resolve)
@@ -103,6 +219,14 @@ async fn run(client: Client) -> std::io::Result<()> {
             "/{address_id}",
             web::patch().to(controller::address::update),
           )
+          .route(
+            "/{address_id}",
+            web::delete().to(controller::address::delete),
+          )
+          .route(
+            "/{address_id}/default",
+            web::post().to(controller::address::set_default),
+          )
           .route("", web::get().to(controller::address::get_all)),
       )
       .service(
@@ -110,8 +234,46 @@ async fn run(client: Client) -> std::io::Result<()> {
           .wrap(middleware::user::Resolve)
           .route("", web::post().to(controller::order::create))
           .route("/{id}", web::get().to(controller::order::find))
+          .route("/{id}/cancel", web::post().to(controller::order::cancel))
+          .service(
+            web::resource("/{id}/status")
+              .wrap(middleware::role::RequireRole::any(&[Role::Admin]))
+              .route(web::patch().to(controller::order::advance_status)),
+          )
           .route("", web::get().to(controller::order::get_all)),
       )
+      .service(
+        web::scope("/seller/listings")
+          .wrap(middleware::role::RequireRole::any(&[Role::Seller]))
+          .wrap(middleware::user::Resolve)
+          .route("", web::get().to(controller::listing::get_own))
+          .route("", web::post().to(controller::listing::create))
+          .route("/{id}", web::patch().to(controller::listing::update))
+          .route("/{id}", web::delete().to(controller::listing::delete)),
+      )
+      .service(
+        web::scope("/seller/orders")
+          .wrap(middleware::role::RequireRole::any(&[Role::Seller]))
+          .wrap(middleware::user::Resolve)
+          .route("", web::get().to(controller::order::get_all_for_seller))
+          .route(
+            "/{id}/status",
+            web::patch().to(controller::order::advance_sub_order_status),
+          ),
+      )
+      .service(web::resource("/search").route(web::get().to(controller::search::search)))
+      .service(
+        web::scope("/products")
+          .route("/{id}", web::get().to(controller::product::find)),
+      )
+      .service(
+        web::scope("/locations")
+          .route("/districts", web::get().to(controller::location::get_districts))
+          .route(
+            "/districts/{id}/neighborhoods",
+            web::get().to(controller::location::get_neighborhoods),
+          ),
+      )
       .service(
         web::scope("/sellers")
           .route("/{name}", web::get().to(controller::seller::get)),
@@ -1 +1,3 @@
-pub mod user;
\ No newline at end of file
+pub mod user;
+pub mod role;
+pub mod validation;
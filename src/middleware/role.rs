@@ -0,0 +1,84 @@
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use super::user::missing_user;
+use crate::error::ApiError;
+use crate::model::user::{AuthUser, Role};
+use actix_service::{Service, Transform};
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+// Must be wrapped inside `user::Resolve` so the authenticated user is already resolved.
+pub struct RequireRole {
+  roles: Rc<Vec<Role>>,
+}
+
+impl RequireRole {
+  pub fn any(roles: &[Role]) -> Self {
+    RequireRole {
+      roles: Rc::new(roles.to_vec()),
+    }
+  }
+}
+
+impl<S, B> Transform<S> for RequireRole
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type InitError = ();
+  type Transform = RequireRoleMiddleware<S>;
+  type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+  fn new_transform(&self, service: S) -> Self::Future {
+    ok(RequireRoleMiddleware {
+      service,
+      roles: self.roles.clone(),
+    })
+  }
+}
+
+pub struct RequireRoleMiddleware<S> {
+  service: S,
+  roles: Rc<Vec<Role>>,
+}
+
+impl<S, B> Service for RequireRoleMiddleware<S>
+where
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S::Future: 'static,
+  B: 'static,
+{
+  type Request = ServiceRequest;
+  type Response = ServiceResponse<B>;
+  type Error = Error;
+  type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.service.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let role = req.extensions().get::<AuthUser>().map(|user| user.role);
+    match role {
+      Some(role) if self.roles.contains(&role) => {
+        let fut = self.service.call(req);
+        Box::pin(async move {
+          let res = fut.await?;
+          Ok(res)
+        })
+      }
+      Some(_role) => Box::pin(async { Err(ApiError::Forbidden("insufficient_role").into()) }),
+      None => {
+        let error = missing_user(&req);
+        Box::pin(async { Err(error.into()) })
+      }
+    }
+  }
+}
@@ -1,17 +1,70 @@
+use std::cell::RefCell;
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll};
 
+use crate::action::token::decode_access_token;
+use crate::error::ApiError;
+use crate::model::user::AuthUser;
 use actix_service::{Service, Transform};
-use actix_web::{dev::ServiceRequest, dev::ServiceResponse, http::header, Error};
-use futures::future::{ok, Ready};
+use actix_web::{
+  dev::Payload, dev::ServiceRequest, dev::ServiceResponse, web, Error, FromRequest, HttpMessage,
+  HttpRequest,
+};
+use bson::oid::ObjectId;
+use futures::future::{err, ok, Ready};
 use futures::Future;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+
+// Why the access token of the request was not accepted. Routes that take a user, even an optional
+// one, answer with it so the client refreshes instead of silently going on as someone else.
+// Routes that never look at the user are not affected by a stale cookie.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenRejection(pub &'static str);
+
+pub fn missing_user<R: HttpMessage>(req: &R) -> ApiError {
+  match req.extensions().get::<TokenRejection>() {
+    Some(rejection) => ApiError::Unauthorized(rejection.0),
+    None => ApiError::Unauthorized("missing_token"),
+  }
+}
+
+impl FromRequest for AuthUser {
+  type Error = ApiError;
+  type Future = Ready<Result<Self, Self::Error>>;
+  type Config = ();
+
+  fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+    match req.extensions().get::<AuthUser>() {
+      Some(user) => ok(user.clone()),
+      None => err(missing_user(req)),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct OptionalAuthUser(pub Option<AuthUser>);
+
+impl FromRequest for OptionalAuthUser {
+  type Error = ApiError;
+  type Future = Ready<Result<Self, Self::Error>>;
+  type Config = ();
+
+  fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+    let extensions = req.extensions();
+    match (extensions.get::<AuthUser>(), extensions.get::<TokenRejection>()) {
+      (Some(user), _) => ok(OptionalAuthUser(Some(user.clone()))),
+      // only a request without an access token is anonymous
+      (None, Some(rejection)) => err(ApiError::Unauthorized(rejection.0)),
+      (None, None) => ok(OptionalAuthUser(None)),
+    }
+  }
+}
 
 pub struct Resolve;
 
 impl<S, B> Transform<S> for Resolve
 where
-  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
   S::Future: 'static,
   B: 'static,
 {
@@ -23,17 +76,44 @@ where
   type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
   fn new_transform(&self, service: S) -> Self::Future {
-    ok(ResolveMiddleware { service })
+    ok(ResolveMiddleware {
+      service: Rc::new(RefCell::new(service)),
+    })
   }
 }
 
 pub struct ResolveMiddleware<S> {
-  service: S,
+  service: Rc<RefCell<S>>,
+}
+
+async fn resolve_user(req: &ServiceRequest, token: &str) -> Result<AuthUser, ApiError> {
+  let claims = decode_access_token(token, true)?;
+  let id = ObjectId::with_string(&claims.sub).map_err(|_e| ApiError::Unauthorized("invalid_token"))?;
+  let app_data = req
+    .app_data::<crate::AppState>()
+    .ok_or(ApiError::Internal(String::from("Application state is not configured")))?;
+  let jti = claims.jti.clone();
+  let revoked =
+    web::block(move || app_data.service_container.token.is_access_revoked(&jti)).await?;
+  if revoked {
+    return Err(ApiError::Unauthorized("token_revoked"));
+  }
+  let seller_id = match claims.seller_id {
+    Some(seller_id) => Some(
+      ObjectId::with_string(&seller_id).map_err(|_e| ApiError::Unauthorized("invalid_token"))?,
+    ),
+    None => None,
+  };
+  Ok(AuthUser {
+    id,
+    role: claims.role,
+    seller_id,
+  })
 }
 
 impl<S, B> Service for ResolveMiddleware<S>
 where
-  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+  S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
   S::Future: 'static,
   B: 'static,
 {
@@ -43,70 +123,25 @@ where
   type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
 
   fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-    self.service.poll_ready(cx)
+    self.service.borrow_mut().poll_ready(cx)
   }
 
-  fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
-    req
-      .headers_mut()
-      .remove(header::HeaderName::from_static("token"));
-    req
-      .headers_mut()
-      .remove(header::HeaderName::from_static("user_id"));
-    req
-      .headers_mut()
-      .remove(header::HeaderName::from_static("user_type"));
-    let mut token = "";
-    let headers = req.headers().clone();
-    match headers.get("cookie") {
-      Some(cookie) => {
-        let split = cookie.to_str().unwrap().split(";");
-        let cookies: Vec<&str> = split.collect();
-        for cookie in &cookies {
-          if cookie.contains("access_token=") {
-            let token_cookie: Vec<&str> = cookie.split("=").collect();
-            token = token_cookie[1];
+  fn call(&mut self, req: ServiceRequest) -> Self::Future {
+    let service = self.service.clone();
+
+    Box::pin(async move {
+      if let Some(cookie) = req.cookie("access_token") {
+        match resolve_user(&req, cookie.value()).await {
+          Ok(user) => {
+            req.extensions_mut().insert(user);
           }
-        }
-      }
-      None => {}
-    };
-    if token.len() > 0 {
-      let split = token.split(".");
-      let token_content: Vec<&str> = split.collect();
-      if token_content.len() == 3 {
-        let validation = Validation {
-          leeway: 0,
-          validate_exp: false,
-          validate_nbf: false,
-          iss: None,
-          sub: None,
-          aud: None,
-          algorithms: vec![Algorithm::HS256],
-        };
-        match decode::<crate::model::user::Claims>(
-          token,
-          &DecodingKey::from_secret(dotenv!("JWT_SECRET").as_ref()),
-          &validation,
-        ) {
-          Ok(decoded_token) => {
-            req.headers_mut().insert(
-              header::HeaderName::from_static("user_id"),
-              header::HeaderValue::from_str(&decoded_token.claims.sub).unwrap(),
-            );
-            req.headers_mut().insert(
-              header::HeaderName::from_static("user_type"),
-              header::HeaderValue::from_str(&decoded_token.claims.user_type).unwrap(),
-            );
+          Err(ApiError::Unauthorized(code)) => {
+            req.extensions_mut().insert(TokenRejection(code));
           }
-          Err(e) => println!("Error while decoding token: {:?}", e),
+          Err(e) => return Err(e.into()),
         }
       }
-    }
-
-    let fut = self.service.call(req);
-
-    Box::pin(async move {
+      let fut = service.borrow_mut().call(req);
       let res = fut.await?;
       Ok(res)
     })
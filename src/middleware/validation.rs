@@ -0,0 +1,45 @@
+use crate::model::validation::Validator;
+use crate::traits::validate::Validate;
+use actix_web::{dev::Payload, web, Error, FromRequest, HttpRequest};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::de::DeserializeOwned;
+use std::ops::Deref;
+
+// A JSON body that passed its `Validate` rules, failing the request with 422 otherwise.
+#[derive(Debug)]
+pub struct Valid<T>(pub T);
+
+impl<T> Valid<T> {
+  pub fn into_inner(self) -> T {
+    self.0
+  }
+}
+
+impl<T> Deref for Valid<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T> FromRequest for Valid<T>
+where
+  T: DeserializeOwned + Validate + 'static,
+{
+  type Error = Error;
+  type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+  type Config = web::JsonConfig;
+
+  fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+    let json = web::Json::<T>::from_request(req, payload);
+    async move {
+      let mut body = json.await?.into_inner();
+      let mut validator = Validator::default();
+      body.validate(&mut validator);
+      validator.finish()?;
+      Ok(Valid(body))
+    }
+    .boxed_local()
+  }
+}
@@ -14,6 +14,10 @@ pub struct Address {
 }
 
 impl Address {
+  pub fn user_id(&self) -> &ObjectId {
+    &self.user_id
+  }
+
   pub fn new(
     user_id: ObjectId,
     name: &str,
@@ -1,3 +1,4 @@
+use crate::model::coupon::DiscountLine;
 use bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
@@ -23,6 +24,22 @@ impl BasketItem {
       count,
     }
   }
+
+  pub fn product_id(&self) -> &ObjectId {
+    &self.product_id
+  }
+
+  pub fn seller_id(&self) -> &ObjectId {
+    &self.seller_id
+  }
+
+  pub fn listing_id(&self) -> &ObjectId {
+    &self.listing_id
+  }
+
+  pub fn count(&self) -> i16 {
+    self.count
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -30,6 +47,8 @@ pub struct Basket {
   user_id: ObjectId,
   content: Vec<BasketItem>,
   active: bool,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  coupon_code: Option<String>,
 }
 
 impl Basket {
@@ -38,6 +57,58 @@ impl Basket {
       user_id,
       content,
       active,
+      coupon_code: None,
     }
   }
+
+  pub fn content(&self) -> &Vec<BasketItem> {
+    &self.content
+  }
+
+  pub fn coupon_code(&self) -> Option<&str> {
+    self.coupon_code.as_deref()
+  }
+
+  pub fn into_content(self) -> Vec<BasketItem> {
+    self.content
+  }
+}
+
+#[derive(Serialize, Debug)]
+pub struct BasketLine {
+  pub listing_id: ObjectId,
+  pub product_id: ObjectId,
+  pub name: String,
+  pub image_url: Option<String>,
+  pub unit_price: f64,
+  pub old_price: Option<f64>,
+  pub count: i32,
+  pub line_total: f64,
+  pub available: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SellerGroup {
+  pub seller_id: ObjectId,
+  pub lines: Vec<BasketLine>,
+  pub subtotal: f64,
+  pub shipping_fee: f64,
+  // set when the seller does not deliver to the summary's address
+  pub delivery_error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BasketSummary {
+  pub id: ObjectId,
+  pub sellers: Vec<SellerGroup>,
+  // shipping is priced for this address, the user's default one
+  pub address_id: Option<ObjectId>,
+  pub item_count: i32,
+  pub subtotal: f64,
+  pub shipping_fee: f64,
+  pub coupon: Option<DiscountLine>,
+  // set when the applied coupon no longer applies to the basket
+  pub coupon_error: Option<String>,
+  pub discount: f64,
+  pub total: f64,
 }
@@ -0,0 +1,38 @@
+use bson::{oid::ObjectId, UtcDateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CouponKind {
+  Percentage,
+  FixedAmount,
+  FreeShipping,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Coupon {
+  #[serde(rename = "_id")]
+  pub id: ObjectId,
+  pub code: String,
+  pub kind: CouponKind,
+  #[serde(default)]
+  pub value: f64,
+  // a seller-scoped coupon only discounts that seller's lines
+  pub seller_id: Option<ObjectId>,
+  pub starts_at: Option<UtcDateTime>,
+  pub ends_at: Option<UtcDateTime>,
+  pub min_basket_value: Option<f64>,
+  pub usage_limit: Option<i32>,
+  pub per_user_limit: Option<i32>,
+  #[serde(default)]
+  pub used_count: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiscountLine {
+  pub coupon_id: ObjectId,
+  pub code: String,
+  pub kind: CouponKind,
+  pub seller_id: Option<ObjectId>,
+  pub amount: f64,
+}
@@ -0,0 +1,41 @@
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ZoneArea {
+  pub district_id: i32,
+  // empty covers the whole district
+  #[serde(default)]
+  pub neighborhood_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryZone {
+  #[serde(rename = "_id")]
+  pub id: ObjectId,
+  pub seller_id: ObjectId,
+  pub areas: Vec<ZoneArea>,
+  pub shipping_fee: f64,
+  #[serde(default)]
+  pub min_order_amount: f64,
+}
+
+impl DeliveryZone {
+  // 2 when the neighborhood is listed, 1 when the whole district is covered.
+  pub fn specificity(&self, district_id: i32, neighborhood_id: i32) -> Option<u8> {
+    self
+      .areas
+      .iter()
+      .filter(|area| area.district_id == district_id)
+      .filter_map(|area| {
+        if area.neighborhood_ids.is_empty() {
+          Some(1)
+        } else if area.neighborhood_ids.contains(&neighborhood_id) {
+          Some(2)
+        } else {
+          None
+        }
+      })
+      .max()
+  }
+}
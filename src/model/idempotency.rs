@@ -0,0 +1,26 @@
+use bson::UtcDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredResponse {
+  pub status: i32,
+  pub content_type: Option<String>,
+  pub body: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IdempotencyRecord {
+  pub key: String,
+  pub scope: String,
+  pub request: String,
+  pub response: Option<StoredResponse>,
+  // a request still running after this is taken to have died and may be retried
+  pub locked_until: Option<UtcDateTime>,
+  pub expires_at: UtcDateTime,
+}
+
+pub enum Claim {
+  New,
+  Replay(StoredResponse),
+  InProgress,
+}
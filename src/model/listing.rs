@@ -0,0 +1,208 @@
+use crate::error::ApiError;
+use crate::model::object_id::ObjectIdParam;
+use crate::model::page::Cursor;
+use bson::oid::ObjectId;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Product {
+  #[serde(default)]
+  pub name: String,
+  pub size: Option<String>,
+  pub price: f64,
+  pub old_price: Option<f64>,
+  pub image_url: Option<String>,
+}
+
+// Tells a field sent as null, `Some(None)`, apart from one left out, `None`.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Deserialize<'de>,
+{
+  Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ProductPatch {
+  pub name: Option<String>,
+  pub size: Option<String>,
+  pub price: Option<f64>,
+  // null ends the discount
+  #[serde(default, deserialize_with = "nullable")]
+  pub old_price: Option<Option<f64>>,
+  pub image_url: Option<String>,
+}
+
+impl ProductPatch {
+  pub fn apply(self, product: &mut Product) {
+    if let Some(name) = self.name {
+      product.name = name;
+    }
+    if let Some(size) = self.size {
+      product.size = Some(size);
+    }
+    if let Some(price) = self.price {
+      product.price = price;
+    }
+    if let Some(old_price) = self.old_price {
+      product.old_price = old_price;
+    }
+    if let Some(image_url) = self.image_url {
+      product.image_url = Some(image_url);
+    }
+  }
+}
+
+impl Product {
+  pub fn validate(&self) -> Result<(), ApiError> {
+    if self.name.trim().is_empty() || self.name.chars().count() > 120 {
+      return Err(ApiError::Validation(String::from(
+        "Product name must be between 1 and 120 characters",
+      )));
+    }
+    if self.price <= 0.0 {
+      return Err(ApiError::Validation(String::from("Price must be greater than 0")));
+    }
+    if let Some(old_price) = self.old_price {
+      if old_price <= self.price {
+        return Err(ApiError::Validation(String::from(
+          "Old price must be greater than price",
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Listing {
+  pub product_id: ObjectId,
+  pub seller_id: ObjectId,
+  #[serde(default)]
+  pub header: String,
+  #[serde(default)]
+  pub text: String,
+  #[serde(rename = "type", default)]
+  pub kind: String,
+  #[serde(default)]
+  pub visible: bool,
+  #[serde(default)]
+  pub homepage: bool,
+  #[serde(default)]
+  pub priority: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stock: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ListingPatch {
+  pub header: Option<String>,
+  pub text: Option<String>,
+  #[serde(rename = "type")]
+  pub kind: Option<String>,
+  pub visible: Option<bool>,
+  pub homepage: Option<bool>,
+  pub priority: Option<i32>,
+  pub stock: Option<i32>,
+}
+
+impl ListingPatch {
+  pub fn apply(self, listing: &mut Listing) {
+    if let Some(header) = self.header {
+      listing.header = header;
+    }
+    if let Some(text) = self.text {
+      listing.text = text;
+    }
+    if let Some(kind) = self.kind {
+      listing.kind = kind;
+    }
+    if let Some(visible) = self.visible {
+      listing.visible = visible;
+    }
+    if let Some(homepage) = self.homepage {
+      listing.homepage = homepage;
+    }
+    if let Some(priority) = self.priority {
+      listing.priority = priority;
+    }
+    if let Some(stock) = self.stock {
+      listing.stock = Some(stock);
+    }
+  }
+}
+
+impl Listing {
+  pub fn new(product_id: ObjectId, seller_id: ObjectId) -> Listing {
+    Listing {
+      product_id,
+      seller_id,
+      header: String::new(),
+      text: String::new(),
+      kind: String::new(),
+      visible: true,
+      homepage: false,
+      priority: 0,
+      stock: None,
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), ApiError> {
+    if self.header.trim().is_empty() || self.header.chars().count() > 120 {
+      return Err(ApiError::Validation(String::from(
+        "Header must be between 1 and 120 characters",
+      )));
+    }
+    if self.text.chars().count() > 2000 {
+      return Err(ApiError::Validation(String::from(
+        "Text can not be longer than 2000 characters",
+      )));
+    }
+    if self.kind.trim().is_empty() || self.kind.chars().count() > 32 {
+      return Err(ApiError::Validation(String::from(
+        "Type must be between 1 and 32 characters",
+      )));
+    }
+    if self.priority < 0 {
+      return Err(ApiError::Validation(String::from("Priority can not be negative")));
+    }
+    if self.stock.map_or(false, |stock| stock < 0) {
+      return Err(ApiError::Validation(String::from("Stock can not be negative")));
+    }
+    Ok(())
+  }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingSort {
+  Priority,
+  PriceAsc,
+  PriceDesc,
+  Newest,
+}
+
+impl ListingSort {
+  // The expression sorted on and its direction; `Newest` sorts on the id alone.
+  pub fn key(self) -> Option<(&'static str, i32)> {
+    match self {
+      ListingSort::Priority => Some(("$priority", -1)),
+      ListingSort::PriceAsc => Some(("$product.price", 1)),
+      ListingSort::PriceDesc => Some(("$product.price", -1)),
+      ListingSort::Newest => None,
+    }
+  }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListingQuery {
+  pub limit: Option<i64>,
+  pub after: Option<Cursor>,
+  pub sort: Option<ListingSort>,
+  pub seller: Option<ObjectIdParam>,
+  #[serde(rename = "type")]
+  pub kind: Option<String>,
+  pub min_price: Option<f64>,
+  pub max_price: Option<f64>,
+}
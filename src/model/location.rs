@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Neighborhood {
+  pub id: i32,
+  pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct District {
+  #[serde(rename = "_id")]
+  pub id: i32,
+  pub name: String,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub neighborhoods: Vec<Neighborhood>,
+}
+
+impl District {
+  pub fn neighborhood(&self, id: i32) -> Option<&Neighborhood> {
+    self.neighborhoods.iter().find(|neighborhood| neighborhood.id == id)
+  }
+}
@@ -1,4 +1,15 @@
 pub mod basket;
 pub mod address;
 pub mod user;
-pub mod order;
\ No newline at end of file
+pub mod order;
+pub mod object_id;
+pub mod token;
+pub mod idempotency;
+pub mod reservation;
+pub mod coupon;
+pub mod listing;
+pub mod page;
+pub mod search;
+pub mod location;
+pub mod delivery_zone;
+pub mod validation;
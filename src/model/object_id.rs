@@ -0,0 +1,33 @@
+use bson::oid::ObjectId;
+use serde::de::{self, Deserialize, Deserializer};
+use std::ops::Deref;
+
+#[derive(Debug, Clone)]
+pub struct ObjectIdParam(ObjectId);
+
+impl ObjectIdParam {
+  pub fn into_inner(self) -> ObjectId {
+    self.0
+  }
+}
+
+impl Deref for ObjectIdParam {
+  type Target = ObjectId;
+
+  fn deref(&self) -> &ObjectId {
+    &self.0
+  }
+}
+
+impl<'de> Deserialize<'de> for ObjectIdParam {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let id = String::deserialize(deserializer)?;
+    match ObjectId::with_string(&id) {
+      Ok(object_id) => Ok(ObjectIdParam(object_id)),
+      Err(_e) => Err(de::Error::custom(format!("{} is not a valid id", id))),
+    }
+  }
+}
@@ -1,12 +1,88 @@
+use crate::model::coupon::DiscountLine;
+use crate::model::page::Cursor;
+use bson::oid::ObjectId;
 use bson::ordered::OrderedDocument;
+use bson::UtcDateTime;
+use serde::de::{self, Deserializer, Visitor};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusChange {
+  status: Status,
+  changed_by: ObjectId,
+  changed_at: UtcDateTime,
+}
+
+impl StatusChange {
+  pub fn new(status: Status, changed_by: ObjectId) -> Self {
+    StatusChange {
+      status,
+      changed_by,
+      changed_at: UtcDateTime(chrono::Utc::now()),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderLine {
+  pub listing_id: ObjectId,
+  pub product_id: ObjectId,
+  pub seller_id: ObjectId,
+  pub name: String,
+  pub count: i32,
+  pub unit_price: f64,
+  pub line_total: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderTotals {
+  pub subtotal: f64,
+  pub shipping_fee: f64,
+  #[serde(default)]
+  pub discount: f64,
+  pub total: f64,
+}
+
+// The part of an order fulfilled by a single seller, with its own status lifecycle.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubOrder {
+  pub seller_id: ObjectId,
+  pub lines: Vec<OrderLine>,
+  pub totals: OrderTotals,
+  pub status: Status,
+  pub status_history: Vec<StatusChange>,
+}
+
+impl SubOrder {
+  pub fn new(
+    seller_id: ObjectId,
+    lines: Vec<OrderLine>,
+    totals: OrderTotals,
+    status: Status,
+    created_by: ObjectId,
+  ) -> Self {
+    SubOrder {
+      seller_id,
+      lines,
+      totals,
+      status,
+      status_history: vec![StatusChange::new(status, created_by)],
+    }
+  }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Order {
   user_id: bson::oid::ObjectId,
   address: OrderedDocument,
   basket: OrderedDocument,
-  status: i32,
+  lines: Vec<OrderLine>,
+  discounts: Vec<DiscountLine>,
+  totals: OrderTotals,
+  sub_orders: Vec<SubOrder>,
+  status: Status,
+  status_history: Vec<StatusChange>,
 }
 
 impl Order {
@@ -14,22 +90,168 @@ impl Order {
     user_id: bson::oid::ObjectId,
     basket: OrderedDocument,
     address: OrderedDocument,
+    lines: Vec<OrderLine>,
+    discounts: Vec<DiscountLine>,
+    totals: OrderTotals,
+    sub_orders: Vec<SubOrder>,
     status: Status,
   ) -> Self {
     Order {
+      status_history: vec![StatusChange::new(status, user_id.clone())],
       user_id,
       address,
       basket,
-      status: status as i32,
+      lines,
+      discounts,
+      totals,
+      sub_orders,
+      status,
     }
   }
+
+  pub fn user_id(&self) -> &ObjectId {
+    &self.user_id
+  }
+
+  pub fn lines(&self) -> &Vec<OrderLine> {
+    &self.lines
+  }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OrderDocument {
+  #[serde(rename = "_id")]
+  pub id: ObjectId,
+  pub user_id: ObjectId,
+  pub address: OrderedDocument,
+  pub basket: OrderedDocument,
+  #[serde(default)]
+  pub lines: Vec<OrderLine>,
+  #[serde(default)]
+  pub discounts: Vec<DiscountLine>,
+  pub totals: Option<OrderTotals>,
+  #[serde(default)]
+  pub sub_orders: Vec<SubOrder>,
+  pub status: Status,
+  #[serde(default)]
+  pub status_history: Vec<StatusChange>,
+  pub created_at: Option<UtcDateTime>,
+  pub updated_at: Option<UtcDateTime>,
+}
+
+impl OrderDocument {
+  pub fn sub_order(&self, seller_id: &ObjectId) -> Option<&SubOrder> {
+    self
+      .sub_orders
+      .iter()
+      .find(|sub_order| &sub_order.seller_id == seller_id)
+  }
+}
+
+// A seller's view of an order: only their sub-order and where to ship it.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SellerOrder {
+  #[serde(rename = "_id")]
+  pub id: ObjectId,
+  pub address: OrderedDocument,
+  pub sub_order: SubOrder,
+  pub created_at: Option<UtcDateTime>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OrderQuery {
+  pub limit: Option<i64>,
+  pub after: Option<Cursor>,
+  pub status: Option<Status>,
+}
+
+// Discriminants are the integers orders were stored with before statuses were persisted by name.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum Status {
-  Cancelled,
-  Taken,
-  Preparing,
-  Shipping,
-  Shipped,
+  Cancelled = 0,
+  Taken = 1,
+  Preparing = 2,
+  Shipping = 3,
+  Shipped = 4,
+}
+
+impl Status {
+  pub const ALL: [Status; 5] = [
+    Status::Cancelled,
+    Status::Taken,
+    Status::Preparing,
+    Status::Shipping,
+    Status::Shipped,
+  ];
+
+  pub fn from_legacy(value: i64) -> Option<Status> {
+    Status::ALL.iter().cloned().find(|status| *status as i64 == value)
+  }
+
+  pub fn from_name(name: &str) -> Option<Status> {
+    Status::ALL.iter().cloned().find(|status| status.name() == name)
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Status::Cancelled => "cancelled",
+      Status::Taken => "taken",
+      Status::Preparing => "preparing",
+      Status::Shipping => "shipping",
+      Status::Shipped => "shipped",
+    }
+  }
+
+  // An order with sub-orders is as far along as its least progressed live sub-order.
+  pub fn of_sub_orders(sub_orders: &[SubOrder]) -> Status {
+    sub_orders
+      .iter()
+      .map(|sub_order| sub_order.status)
+      .filter(|status| *status != Status::Cancelled)
+      .min_by_key(|status| *status as i32)
+      .unwrap_or(Status::Cancelled)
+  }
+
+  pub fn can_transition_to(self, next: Status) -> bool {
+    match (self, next) {
+      (Status::Taken, Status::Preparing) => true,
+      (Status::Preparing, Status::Shipping) => true,
+      (Status::Shipping, Status::Shipped) => true,
+      (Status::Taken, Status::Cancelled) => true,
+      (Status::Preparing, Status::Cancelled) => true,
+      _ => false,
+    }
+  }
+}
+
+struct StatusVisitor;
+
+impl<'de> Visitor<'de> for StatusVisitor {
+  type Value = Status;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+    formatter.write_str("an order status name or legacy status integer")
+  }
+
+  fn visit_str<E: de::Error>(self, value: &str) -> Result<Status, E> {
+    Status::from_name(value).ok_or_else(|| E::custom(format!("unknown order status {}", value)))
+  }
+
+  fn visit_i64<E: de::Error>(self, value: i64) -> Result<Status, E> {
+    Status::from_legacy(value).ok_or_else(|| E::custom(format!("unknown order status {}", value)))
+  }
+
+  fn visit_u64<E: de::Error>(self, value: u64) -> Result<Status, E> {
+    self.visit_i64(value as i64)
+  }
+}
+
+impl<'de> Deserialize<'de> for Status {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(StatusVisitor)
+  }
 }
@@ -0,0 +1,65 @@
+use crate::error::ApiError;
+use bson::oid::ObjectId;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::Serialize;
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+
+pub fn page_limit(limit: Option<i64>) -> Result<i64, ApiError> {
+  match limit {
+    None => Ok(DEFAULT_LIMIT),
+    Some(limit) if (1..=MAX_LIMIT).contains(&limit) => Ok(limit),
+    Some(_limit) => Err(ApiError::Validation(format!(
+      "limit must be between 1 and {}",
+      MAX_LIMIT
+    ))),
+  }
+}
+
+// Position of the last item of a page: its sort key, when the page is not sorted by id
+// alone, and its id to break ties.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+  pub key: Option<f64>,
+  pub id: ObjectId,
+}
+
+impl Cursor {
+  pub fn new(key: Option<f64>, id: ObjectId) -> Self {
+    Cursor { key, id }
+  }
+
+  pub fn encode(&self) -> String {
+    match self.key {
+      Some(key) => format!("{}_{}", key, self.id.to_hex()),
+      None => self.id.to_hex(),
+    }
+  }
+
+  fn decode(value: &str) -> Option<Cursor> {
+    match value.rfind('_') {
+      Some(index) => Some(Cursor::new(
+        Some(value[..index].parse::<f64>().ok()?),
+        ObjectId::with_string(&value[index + 1..]).ok()?,
+      )),
+      None => Some(Cursor::new(None, ObjectId::with_string(value).ok()?)),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let value = String::deserialize(deserializer)?;
+    Cursor::decode(&value).ok_or_else(|| de::Error::custom(format!("{} is not a valid cursor", value)))
+  }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+  pub items: Vec<T>,
+  pub next_cursor: Option<String>,
+}
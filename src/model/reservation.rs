@@ -0,0 +1,10 @@
+use bson::{oid::ObjectId, UtcDateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Reservation {
+  pub listing_id: ObjectId,
+  pub user_id: ObjectId,
+  pub count: i32,
+  pub expires_at: UtcDateTime,
+}
@@ -0,0 +1,33 @@
+use crate::model::object_id::ObjectIdParam;
+use bson::ordered::OrderedDocument;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+  pub q: String,
+  pub limit: Option<i64>,
+  pub offset: Option<usize>,
+  pub seller: Option<ObjectIdParam>,
+  #[serde(rename = "type")]
+  pub kind: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Facet {
+  pub value: String,
+  pub count: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Facets {
+  pub sellers: Vec<Facet>,
+  pub types: Vec<Facet>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+  pub items: Vec<OrderedDocument>,
+  pub total: usize,
+  // counted over every match of `q`, before the seller and type filters
+  pub facets: Facets,
+}
@@ -0,0 +1,24 @@
+use bson::{oid::ObjectId, UtcDateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RefreshToken {
+  pub user_id: ObjectId,
+  pub token: String,
+  pub expires_at: UtcDateTime,
+}
+
+impl RefreshToken {
+  pub fn new(user_id: ObjectId, token: &str, expires_at: UtcDateTime) -> Self {
+    RefreshToken {
+      user_id,
+      token: String::from(token),
+      expires_at,
+    }
+  }
+}
+
+pub struct TokenPair {
+  pub access_token: String,
+  pub refresh_token: String,
+}
@@ -1,3 +1,4 @@
+use bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,8 +16,71 @@ impl User {
   }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+  Guest,
+  #[serde(alias = "registered")]
+  Customer,
+  Seller,
+  Admin,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserDocument {
+  pub _id: ObjectId,
+  pub phone: Option<String>,
+  pub password: Option<String>,
+  pub role: Option<Role>,
+  pub seller_id: Option<ObjectId>,
+}
+
+impl UserDocument {
+  pub fn auth_user(&self) -> AuthUser {
+    let role = match (self.role, &self.phone) {
+      (Some(role), _) => role,
+      (None, Some(_phone)) => Role::Customer,
+      (None, None) => Role::Guest,
+    };
+    AuthUser {
+      id: self._id.clone(),
+      role,
+      seller_id: self.seller_id.clone(),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+  pub id: ObjectId,
+  pub role: Role,
+  pub seller_id: Option<ObjectId>,
+}
+
+impl AuthUser {
+  pub fn guest(id: ObjectId) -> Self {
+    AuthUser {
+      id,
+      role: Role::Guest,
+      seller_id: None,
+    }
+  }
+
+  pub fn customer(id: ObjectId) -> Self {
+    AuthUser {
+      id,
+      role: Role::Customer,
+      seller_id: None,
+    }
+  }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
   pub sub: String,
-  pub user_type: String,
+  pub role: Role,
+  pub seller_id: Option<String>,
+  pub exp: i64,
+  pub iat: i64,
+  pub jti: String,
 }
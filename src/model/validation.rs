@@ -0,0 +1,174 @@
+use crate::error::ApiError;
+use serde::Serialize;
+use std::fmt::Display;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+  pub field: &'static str,
+  pub code: &'static str,
+  pub message: String,
+}
+
+impl FieldError {
+  pub fn new(field: &'static str, code: &'static str, message: String) -> Self {
+    FieldError {
+      field,
+      code,
+      message,
+    }
+  }
+}
+
+// Collects every failing field of a body so the client can show them all at once.
+#[derive(Default)]
+pub struct Validator {
+  errors: Vec<FieldError>,
+}
+
+impl Validator {
+  pub fn string<'a>(&'a mut self, field: &'static str, value: &'a mut String) -> StringRules<'a> {
+    StringRules {
+      validator: self,
+      field,
+      value,
+      failed: false,
+    }
+  }
+
+  pub fn range<T: PartialOrd + Display>(&mut self, field: &'static str, value: T, min: T, max: T) {
+    if value < min || value > max {
+      self.errors.push(FieldError::new(
+        field,
+        "out_of_range",
+        format!("Must be between {} and {}", min, max),
+      ));
+    }
+  }
+
+  pub fn min<T: PartialOrd + Display>(&mut self, field: &'static str, value: T, min: T) {
+    if value < min {
+      self.errors.push(FieldError::new(
+        field,
+        "out_of_range",
+        format!("Must be at least {}", min),
+      ));
+    }
+  }
+
+  pub fn finish(self) -> Result<(), ApiError> {
+    if self.errors.is_empty() {
+      Ok(())
+    } else {
+      Err(ApiError::InvalidFields(self.errors))
+    }
+  }
+}
+
+// Rules run in the order they are chained and stop at the first failure, so a field reports
+// one error at most.
+pub struct StringRules<'a> {
+  validator: &'a mut Validator,
+  field: &'static str,
+  value: &'a mut String,
+  failed: bool,
+}
+
+impl<'a> StringRules<'a> {
+  fn check(mut self, valid: bool, code: &'static str, message: impl FnOnce() -> String) -> Self {
+    if !self.failed && !valid {
+      self
+        .validator
+        .errors
+        .push(FieldError::new(self.field, code, message()));
+      self.failed = true;
+    }
+    self
+  }
+
+  pub fn trimmed(self) -> Self {
+    let trimmed = self.value.trim().to_string();
+    *self.value = trimmed;
+    self
+  }
+
+  pub fn not_empty(self) -> Self {
+    let valid = !self.value.is_empty();
+    self.check(valid, "required", || String::from("Must not be empty"))
+  }
+
+  // counts characters rather than bytes, Turkish letters take two bytes
+  pub fn length(self, min: usize, max: usize) -> Self {
+    let length = self.value.chars().count();
+    self.check(length >= min && length <= max, "invalid_length", || {
+      format!("Must be between {} and {} characters", min, max)
+    })
+  }
+
+  pub fn chars(self, allowed: fn(char) -> bool, description: &'static str) -> Self {
+    let valid = self.value.chars().all(allowed);
+    self.check(valid, "invalid_characters", || {
+      format!("May only contain {}", description)
+    })
+  }
+
+  pub fn phone(self) -> Self {
+    if self.failed {
+      return self;
+    }
+    match normalize_phone(self.value) {
+      Some(phone) => {
+        *self.value = phone;
+        self
+      }
+      None => self.check(false, "invalid_phone", || {
+        String::from("Must be a Turkish mobile phone number")
+      }),
+    }
+  }
+}
+
+pub fn is_name_char(c: char) -> bool {
+  c.is_alphabetic() || c == ' ' || c == '-' || c == '\'' || c == '.'
+}
+
+// Accepts the usual ways of writing a Turkish mobile number, such as "0532 123 45 67" or
+// "+90 (532) 123-4567", and returns it in E.164 form.
+pub fn normalize_phone(value: &str) -> Option<String> {
+  let value = value.trim();
+  let international = value.starts_with('+');
+  let mut digits = String::new();
+  for c in value.trim_start_matches('+').chars() {
+    match c {
+      '0'..='9' => digits.push(c),
+      ' ' | '-' | '(' | ')' => {}
+      _ => return None,
+    }
+  }
+  let national = match digits.len() {
+    12 if digits.starts_with("90") => &digits[2..],
+    11 if !international && digits.starts_with('0') => &digits[1..],
+    10 if !international => &digits[..],
+    _ => return None,
+  };
+  if !national.starts_with('5') {
+    return None;
+  }
+  Some(format!("+90{}", national))
+}
+
+// The forms a phone may have been stored in before registration normalized them, the E.164
+// form first.
+pub fn phone_forms(phone: &str) -> Vec<String> {
+  let mut forms = vec![];
+  if let Some(normalized) = normalize_phone(phone) {
+    let national = normalized[3..].to_string();
+    forms.push(format!("0{}", national));
+    forms.push(format!("90{}", national));
+    forms.insert(0, normalized);
+    forms.push(national);
+  }
+  if !forms.iter().any(|form| form == phone) {
+    forms.push(phone.to_string());
+  }
+  forms
+}
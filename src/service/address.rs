@@ -1,13 +1,11 @@
+use crate::error::ApiError;
 use crate::model::address::Address;
-use crate::traits::service::{Creator, Finder, Getter, Updater};
+use crate::service::is_duplicate_key;
+use crate::traits::service::{Creator, Getter, Updater};
 use bson::{doc, ordered};
 use bson::{oid::ObjectId, to_bson, Bson};
-use mongodb::{
-  error::{Error, ErrorKind},
-  results::InsertOneResult,
-  results::UpdateResult,
-  Collection,
-};
+use mongodb::options::FindOneOptions;
+use mongodb::{results::InsertOneResult, results::UpdateResult, Collection, Database};
 use std::vec;
 
 #[derive(Clone)]
@@ -19,70 +17,126 @@ impl AddressService {
   pub fn new(collection: Collection) -> AddressService {
     AddressService { collection }
   }
+
+  // A user can have only one default address.
+  pub fn ensure_indexes(&self, database: &Database) -> Result<(), ApiError> {
+    database.run_command(
+      doc! {
+        "createIndexes": self.collection.name(),
+        "indexes": [{
+          "key": {"user_id": 1},
+          "name": "user_id_default",
+          "unique": true,
+          "partialFilterExpression": {"is_default": true}
+        }]
+      },
+      None,
+    )?;
+    Ok(())
+  }
+
+  // Addresses are only ever read through their owner, so a foreign id looks like a missing one.
+  // Soft deleted addresses are kept for the orders that embed them but are otherwise hidden.
+  pub fn find_owned(
+    &self,
+    id: &ObjectId,
+    user_id: &ObjectId,
+  ) -> Result<Option<ordered::OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(
+      doc! {"_id": id.clone(), "user_id": user_id.clone(), "deleted_at": {"$exists": false}},
+      None,
+    )?)
+  }
+
+  pub fn find_default(&self, user_id: &ObjectId) -> Result<Option<ordered::OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(
+      doc! {"user_id": user_id.clone(), "is_default": true, "deleted_at": {"$exists": false}},
+      None,
+    )?)
+  }
+
+  pub fn find_latest(&self, user_id: &ObjectId) -> Result<Option<ordered::OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(
+      doc! {"user_id": user_id.clone(), "deleted_at": {"$exists": false}},
+      FindOneOptions {
+        sort: Some(doc! {"_id": -1}),
+        ..Default::default()
+      },
+    )?)
+  }
+
+  // Returns false when the user has no such address. The unique index refuses a second default,
+  // so the old one is cleared first; when a concurrent call sets its own default in between,
+  // clearing again takes over from it.
+  pub fn set_default(&self, id: &ObjectId, user_id: &ObjectId) -> Result<bool, ApiError> {
+    if self.find_owned(id, user_id)?.is_none() {
+      return Ok(false);
+    }
+    for _attempt in 0..3 {
+      self.collection.update_many(
+        doc! {"_id": {"$ne": id.clone()}, "user_id": user_id.clone(), "is_default": true},
+        doc! {"$set": {"is_default": false}},
+        None,
+      )?;
+      match self.collection.update_one(
+        doc! {"_id": id.clone(), "user_id": user_id.clone(), "deleted_at": {"$exists": false}},
+        doc! {"$set": {"is_default": true}},
+        None,
+      ) {
+        Ok(result) => return Ok(result.matched_count == 1),
+        Err(e) if is_duplicate_key(&e) => continue,
+        Err(e) => return Err(e.into()),
+      }
+    }
+    Err(ApiError::Conflict("default_address_changed"))
+  }
+
+  pub fn soft_delete(&self, id: &ObjectId, user_id: &ObjectId) -> Result<Option<ordered::OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one_and_update(
+      doc! {"_id": id.clone(), "user_id": user_id.clone(), "deleted_at": {"$exists": false}},
+      doc! {"$set": {"deleted_at": chrono::Utc::now(), "is_default": false}},
+      None,
+    )?)
+  }
 }
 
 impl Creator<Address> for AddressService {
-  fn create(&self, address: &Address) -> Result<InsertOneResult, Error> {
-    let serialized_address = to_bson(&address).unwrap();
-    if let Bson::Document(mut document) = serialized_address {
+  fn create(&self, address: &Address) -> Result<InsertOneResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&address)? {
+      document.insert("is_default", false);
       document.insert("created_at", chrono::Utc::now());
-      match self.collection.insert_one(document, None) {
-        Ok(insert_result) => Ok(insert_result),
-        Err(e) => Err(e),
-      }
+      Ok(self.collection.insert_one(document, None)?)
     } else {
-      Err(Error::from(ErrorKind::OperationError {
-        message: String::from("Can not create address"),
-      }))
+      Err(ApiError::Internal(String::from("Can not create address")))
     }
   }
 }
 
-impl Getter for AddressService {
-  fn get_all(&self, id: &str) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, String> {
-    match self.collection.find(
-      doc! {"user_id": ObjectId::with_string(id).expect("user_id is not valid")},
+impl Getter<ordered::OrderedDocument> for AddressService {
+  fn get_all(&self, id: &ObjectId) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, ApiError> {
+    let cursor = self.collection.find(
+      doc! {"user_id": id.clone(), "deleted_at": {"$exists": false}},
       None,
-    ) {
-      Ok(cursor) => {
-        let mut addresses: Vec<ordered::OrderedDocument> = vec![];
-        for result in cursor {
-          if let Ok(document) = result {
-            addresses.push(document);
-          } else {
-            return Err(String::from("Can't find addresses"));
-          }
-        }
-        Ok(addresses)
-      }
-      Err(_e) => Err(String::from("Error while getting addresses")),
+    )?;
+    let mut addresses: Vec<ordered::OrderedDocument> = vec![];
+    for result in cursor {
+      addresses.push(result?);
     }
+    Ok(addresses)
   }
 }
 
 impl Updater<Address> for AddressService {
-  fn update(&self, address: &Address, id: &str) -> Result<UpdateResult, Error> {
-    let serialized_address = to_bson(&address).unwrap();
-    if let Bson::Document(mut document) = serialized_address {
+  fn update(&self, address: &Address, id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&address)? {
       document.insert("updated_at", chrono::Utc::now());
-      self.collection.replace_one(
-        doc! {"_id": ObjectId::with_string(id).expect("address id not valid")},
-        document,
+      Ok(self.collection.update_one(
+        doc! {"_id": id.clone(), "user_id": address.user_id().clone(), "deleted_at": {"$exists": false}},
+        doc! {"$set": document},
         None,
-      )
+      )?)
     } else {
-      Err(Error::from(ErrorKind::OperationError {
-        message: String::from("Can not update address"),
-      }))
+      Err(ApiError::Internal(String::from("Can not update address")))
     }
   }
 }
-
-impl Finder for AddressService {
-  fn find(&self, id: &str) -> Result<Option<bson::ordered::OrderedDocument>, Error> {
-    self.collection.find_one(
-      doc! {"_id": ObjectId::with_string(id).expect("Id not valid")},
-      None,
-    )
-  }
-}
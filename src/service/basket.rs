@@ -1,7 +1,8 @@
+use crate::error::ApiError;
 use crate::model::basket::{Basket, BasketItem};
-use bson::{doc, oid::ObjectId, ordered::OrderedDocument, to_bson, Bson};
+use crate::service::session::Transaction;
+use bson::{doc, from_bson, oid::ObjectId, ordered::OrderedDocument, to_bson, Bson};
 use mongodb::{
-  error::{Error, ErrorKind},
   results::{InsertOneResult, UpdateResult},
   Collection,
 };
@@ -16,121 +17,148 @@ impl BasketService {
     BasketService { collection }
   }
 
-  pub fn get_active(&self, user_id: &str) -> Result<Option<OrderedDocument>, Error> {
-    let pipeline = vec![
-      doc! {
-        "$match": doc! {"active": true, "user_id": ObjectId::with_string(user_id).expect("Id not valid")}
-      },
-      doc! {
-        "$lookup": doc! {"from": "product", "localField": "content.product_id", "foreignField": "_id", "as": "product_info"}
-      },
-      doc! {
-        "$unwind": doc! {"path": "$content.product", "preserveNullAndEmptyArrays": true}
-      },
-    ];
-    match self.collection.aggregate(pipeline.into_iter(), None) {
-      Ok(cursor) => {
-        let mut baskets: Vec<OrderedDocument> = vec![];
-        for result in cursor {
-          if let Ok(document) = result {
-            baskets.push(document);
-          } else {
-            return Err(Error::from(ErrorKind::OperationError {
-              message: String::from("Can't find active basket"),
-            }));
-          }
-        }
-        if baskets.len() > 0 {
-          let basket = baskets[0].clone();
-          Ok(Some(basket))
-        } else {
-          Ok(None)
-        }
-      }
-      Err(e) => Err(e),
+  pub fn get_active(&self, user_id: &ObjectId) -> Result<Option<OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(
+      doc! {"active": true, "user_id": user_id.clone()},
+      None,
+    )?)
+  }
+
+  pub fn find_active(&self, user_id: &ObjectId) -> Result<Option<Basket>, ApiError> {
+    let query = doc! {
+      "user_id": user_id.clone(),
+      "active": true
+    };
+    match self.collection.find_one(query, None)? {
+      Some(document) => Ok(Some(from_bson::<Basket>(Bson::Document(document))?)),
+      None => Ok(None),
     }
   }
 
-  pub fn create(&self, basket: &Basket) -> Result<InsertOneResult, Error> {
-    let serialized_basket = to_bson(&basket).unwrap();
-    if let Bson::Document(mut document) = serialized_basket {
+  pub fn create(&self, basket: &Basket) -> Result<InsertOneResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&basket)? {
       document.insert("created_at", chrono::Utc::now());
-      match self.collection.insert_one(document, None) {
-        Ok(insert_result) => Ok(insert_result),
-        Err(e) => Err(e),
-      }
+      Ok(self.collection.insert_one(document, None)?)
     } else {
-      Err(Error::from(ErrorKind::OperationError {
-        message: String::from("Can not create basket"),
-      }))
+      Err(ApiError::Internal(String::from("Can not create basket")))
     }
   }
 
   pub fn update_product_count(
     &self,
-    listing_id: &str,
-    user_id: &str,
+    listing_id: &ObjectId,
+    user_id: &ObjectId,
     count: i32,
-  ) -> Result<Option<OrderedDocument>, Error> {
+  ) -> Result<Option<OrderedDocument>, ApiError> {
     let query = doc! {
-      "user_id": ObjectId::with_string(user_id).expect("Id not valid"),
-      "content.listing_id": ObjectId::with_string(listing_id).expect("listing_id not valid"),
+      "user_id": user_id.clone(),
+      "content.listing_id": listing_id.clone(),
       "active": true
     };
     let update = doc! {"$inc": {"content.$.count": count}};
-    self.collection.find_one_and_update(query, update, None)
+    Ok(self.collection.find_one_and_update(query, update, None)?)
   }
 
-  pub fn add_item(
+  pub fn set_product_count(
     &self,
-    product_id: &str,
-    seller_id: &str,
-    listing_id: &str,
-    user_id: &str,
-  ) -> Result<UpdateResult, Error> {
-    let basket_item = BasketItem::new(
-      ObjectId::with_string(product_id).expect("product_id not valid"),
-      ObjectId::with_string(seller_id).expect("seller_id not valid"),
-      ObjectId::with_string(listing_id).expect("listing_id not valid"),
-      1,
-    );
-    match to_bson(&basket_item) {
-      Ok(basket_item_doc) => self.collection.update_one(
-        doc! {"active": true, "user_id": ObjectId::with_string(&user_id).expect("Id not valid")},
-        doc! {"$push": {"content": basket_item_doc}},
-        None,
-      ),
-      _ => Err(Error::from(ErrorKind::OperationError {
-        message: String::from("Can not create basket"),
-      })),
-    }
+    listing_id: &ObjectId,
+    user_id: &ObjectId,
+    count: i32,
+  ) -> Result<Option<OrderedDocument>, ApiError> {
+    let query = doc! {
+      "user_id": user_id.clone(),
+      "content.listing_id": listing_id.clone(),
+      "active": true
+    };
+    let update = doc! {"$set": {"content.$.count": count}};
+    Ok(self.collection.find_one_and_update(query, update, None)?)
   }
 
-  pub fn get_product_with_count_one(
+  pub fn add_item(
     &self,
-    listing_id: String,
-    user_id: String,
-  ) -> Result<Option<OrderedDocument>, Error> {
-    self.collection.find_one(
-      doc! {"user_id": ObjectId::with_string(&user_id).expect("user_id not valid"),"content": {"$elemMatch": {"listing_id": ObjectId::with_string(&listing_id).expect("listing_id not valid"), "count": 1}}, "active": true},
-      None
-    )
-  }
-
-  pub fn remove_product(&self, listing_id: &str, user_id: &str) -> Result<UpdateResult, Error> {
-    self.collection.update_one(
-      doc! {"active": true, "user_id": ObjectId::with_string(&user_id).expect("Id not valid")},
-      doc! {"$pull": {"content": {"listing_id": ObjectId::with_string(&listing_id).expect("listing_id not valid")}}},
+    product_id: &ObjectId,
+    seller_id: &ObjectId,
+    listing_id: &ObjectId,
+    user_id: &ObjectId,
+  ) -> Result<UpdateResult, ApiError> {
+    let basket_item = BasketItem::new(product_id.clone(), seller_id.clone(), listing_id.clone(), 1);
+    self.push_item(user_id, &basket_item)
+  }
+
+  pub fn push_item(&self, user_id: &ObjectId, basket_item: &BasketItem) -> Result<UpdateResult, ApiError> {
+    let basket_item_doc = to_bson(basket_item)?;
+    Ok(self.collection.update_one(
+      doc! {"active": true, "user_id": user_id.clone()},
+      doc! {"$push": {"content": basket_item_doc}},
+      None,
+    )?)
+  }
+
+  pub fn remove_product(&self, listing_id: &ObjectId, user_id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"active": true, "user_id": user_id.clone()},
+      doc! {"$pull": {"content": {"listing_id": listing_id.clone()}}},
+      None,
+    )?)
+  }
+
+  pub fn set_coupon(&self, user_id: &ObjectId, code: &str) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"active": true, "user_id": user_id.clone()},
+      doc! {"$set": {"coupon_code": code}},
+      None,
+    )?)
+  }
+
+  pub fn remove_coupon(&self, user_id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"active": true, "user_id": user_id.clone()},
+      doc! {"$unset": {"coupon_code": ""}},
+      None,
+    )?)
+  }
+
+  pub fn clear(&self, user_id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"active": true, "user_id": user_id.clone()},
+      doc! {"$set": {"content": []}},
+      None,
+    )?)
+  }
+
+  pub fn check_out(&self, basket_id: &ObjectId) -> Result<Option<OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one_and_update(
+      doc! {"_id": basket_id.clone(), "active": true},
+      doc! {"$set": {"active": false, "checked_out_at": chrono::Utc::now()}},
+      None,
+    )?)
+  }
+
+  pub fn check_out_in(&self, transaction: &mut Transaction, basket_id: &ObjectId) -> Result<bool, ApiError> {
+    let response = transaction.run(doc! {
+      "update": self.collection.name(),
+      "updates": [{
+        "q": {"_id": basket_id.clone(), "active": true},
+        "u": {"$set": {"active": false, "checked_out_at": chrono::Utc::now()}}
+      }]
+    })?;
+    Ok(response.get_i32("n").unwrap_or(0) == 1)
+  }
+
+  pub fn reactivate(&self, basket_id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"_id": basket_id.clone(), "active": false},
+      doc! {"$set": {"active": true}, "$unset": {"checked_out_at": ""}},
       None,
-    )
+    )?)
   }
 
-  pub fn delete(&self, user_id: &str) -> Result<Option<OrderedDocument>, Error> {
+  pub fn delete(&self, user_id: &ObjectId) -> Result<Option<OrderedDocument>, ApiError> {
     let query = doc! {
-      "user_id": ObjectId::with_string(user_id).expect("Id not valid"),
+      "user_id": user_id.clone(),
       "active": true
     };
     let update = doc! {"$set": {"active": false}};
-    self.collection.find_one_and_update(query, update, None)
+    Ok(self.collection.find_one_and_update(query, update, None)?)
   }
 }
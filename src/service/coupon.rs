@@ -0,0 +1,150 @@
+use crate::error::ApiError;
+use crate::model::coupon::Coupon;
+use crate::service::is_duplicate_key;
+use crate::service::session::Transaction;
+use bson::{doc, from_bson, oid::ObjectId, ordered::OrderedDocument, Bson};
+use mongodb::options::UpdateOptions;
+use mongodb::{Collection, Database};
+
+// `redemptions` counts each user's uses of coupons with a per-user limit, one document per
+// coupon and user, so the limit is enforced by the same conditional `$inc` as the usage limit.
+#[derive(Clone)]
+pub struct CouponService {
+  collection: Collection,
+  redemptions: Collection,
+}
+
+fn redeem_query(coupon: &Coupon) -> OrderedDocument {
+  let mut query = doc! {"_id": coupon.id.clone()};
+  if let Some(usage_limit) = coupon.usage_limit {
+    query.insert("$expr", doc! {"$lt": [{"$ifNull": ["$used_count", 0]}, usage_limit]});
+  }
+  query
+}
+
+fn redemption_query(coupon_id: &ObjectId, user_id: &ObjectId) -> OrderedDocument {
+  doc! {"coupon_id": coupon_id.clone(), "user_id": user_id.clone()}
+}
+
+impl CouponService {
+  pub fn new(collection: Collection, redemptions: Collection) -> Self {
+    CouponService {
+      collection,
+      redemptions,
+    }
+  }
+
+  pub fn ensure_indexes(&self, database: &Database) -> Result<(), ApiError> {
+    database.run_command(
+      doc! {
+        "createIndexes": self.redemptions.name(),
+        "indexes": [{
+          "key": {"coupon_id": 1, "user_id": 1},
+          "name": "coupon_id_user_id",
+          "unique": true
+        }]
+      },
+      None,
+    )?;
+    Ok(())
+  }
+
+  pub fn find_by_code(&self, code: &str) -> Result<Option<Coupon>, ApiError> {
+    match self
+      .collection
+      .find_one(doc! {"code": code.to_uppercase()}, None)?
+    {
+      Some(document) => Ok(Some(from_bson::<Coupon>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
+  }
+
+  // Creates the user's counter outside of any transaction, so redeeming only increments it.
+  fn ensure_redemption(&self, coupon_id: &ObjectId, user_id: &ObjectId) -> Result<(), ApiError> {
+    match self.redemptions.update_one(
+      redemption_query(coupon_id, user_id),
+      doc! {"$setOnInsert": {"count": 0}},
+      UpdateOptions {
+        upsert: Some(true),
+        ..Default::default()
+      },
+    ) {
+      Ok(_) => Ok(()),
+      // a concurrent checkout created it first
+      Err(e) if is_duplicate_key(&e) => Ok(()),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  pub fn redeem_in(
+    &self,
+    transaction: &mut Transaction,
+    coupon: &Coupon,
+    user_id: &ObjectId,
+  ) -> Result<(), ApiError> {
+    if let Some(per_user_limit) = coupon.per_user_limit {
+      self.ensure_redemption(&coupon.id, user_id)?;
+      let mut query = redemption_query(&coupon.id, user_id);
+      query.insert("count", doc! {"$lt": per_user_limit});
+      let response = transaction.run(doc! {
+        "update": self.redemptions.name(),
+        "updates": [{"q": query, "u": {"$inc": {"count": 1}}}]
+      })?;
+      if response.get_i32("n").unwrap_or(0) != 1 {
+        return Err(ApiError::Conflict("coupon_user_limit_reached"));
+      }
+    }
+    let response = transaction.run(doc! {
+      "update": self.collection.name(),
+      "updates": [{
+        "q": redeem_query(coupon),
+        "u": {"$inc": {"used_count": 1}}
+      }]
+    })?;
+    if response.get_i32("n").unwrap_or(0) != 1 {
+      return Err(ApiError::Conflict("coupon_usage_limit_reached"));
+    }
+    Ok(())
+  }
+
+  pub fn redeem(&self, coupon: &Coupon, user_id: &ObjectId) -> Result<(), ApiError> {
+    if let Some(per_user_limit) = coupon.per_user_limit {
+      self.ensure_redemption(&coupon.id, user_id)?;
+      let mut query = redemption_query(&coupon.id, user_id);
+      query.insert("count", doc! {"$lt": per_user_limit});
+      let result = self
+        .redemptions
+        .update_one(query, doc! {"$inc": {"count": 1}}, None)?;
+      if result.matched_count == 0 {
+        return Err(ApiError::Conflict("coupon_user_limit_reached"));
+      }
+    }
+    let result = self
+      .collection
+      .update_one(redeem_query(coupon), doc! {"$inc": {"used_count": 1}}, None)?;
+    if result.matched_count == 0 {
+      self.release_redemption(&coupon.id, user_id)?;
+      return Err(ApiError::Conflict("coupon_usage_limit_reached"));
+    }
+    Ok(())
+  }
+
+  // Gives back one use, both of the coupon and of the user's limit.
+  pub fn unredeem(&self, coupon_id: &ObjectId, user_id: &ObjectId) -> Result<(), ApiError> {
+    self.collection.update_one(
+      doc! {"_id": coupon_id.clone(), "used_count": {"$gt": 0}},
+      doc! {"$inc": {"used_count": -1}},
+      None,
+    )?;
+    self.release_redemption(coupon_id, user_id)
+  }
+
+  fn release_redemption(&self, coupon_id: &ObjectId, user_id: &ObjectId) -> Result<(), ApiError> {
+    let mut query = redemption_query(coupon_id, user_id);
+    query.insert("count", doc! {"$gt": 0});
+    self
+      .redemptions
+      .update_one(query, doc! {"$inc": {"count": -1}}, None)?;
+    Ok(())
+  }
+}
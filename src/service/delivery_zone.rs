@@ -0,0 +1,27 @@
+use crate::error::ApiError;
+use crate::model::delivery_zone::DeliveryZone;
+use bson::{doc, from_bson, oid::ObjectId, Bson};
+use mongodb::Collection;
+
+#[derive(Clone)]
+pub struct DeliveryZoneService {
+  collection: Collection,
+}
+
+impl DeliveryZoneService {
+  pub fn new(collection: Collection) -> Self {
+    DeliveryZoneService { collection }
+  }
+
+  pub fn get_for_sellers(&self, seller_ids: &[ObjectId]) -> Result<Vec<DeliveryZone>, ApiError> {
+    let seller_ids: Vec<Bson> = seller_ids.iter().cloned().map(Bson::ObjectId).collect();
+    let cursor = self
+      .collection
+      .find(doc! {"seller_id": {"$in": seller_ids}}, None)?;
+    let mut zones: Vec<DeliveryZone> = vec![];
+    for result in cursor {
+      zones.push(from_bson::<DeliveryZone>(Bson::Document(result?))?);
+    }
+    Ok(zones)
+  }
+}
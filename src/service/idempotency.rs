@@ -0,0 +1,123 @@
+use crate::error::ApiError;
+use crate::model::idempotency::{Claim, IdempotencyRecord, StoredResponse};
+use bson::{doc, from_bson, to_bson, Bson, UtcDateTime};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+
+#[derive(Clone)]
+pub struct IdempotencyService {
+  collection: Collection,
+}
+
+impl IdempotencyService {
+  pub fn new(collection: Collection) -> Self {
+    IdempotencyService { collection }
+  }
+
+  pub fn ensure_indexes(&self, database: &Database) -> Result<(), ApiError> {
+    database.run_command(
+      doc! {
+        "createIndexes": self.collection.name(),
+        "indexes": [
+          {"key": {"key": 1, "scope": 1}, "name": "key_scope", "unique": true},
+          {"key": {"expires_at": 1}, "name": "expires_at_ttl", "expireAfterSeconds": 0}
+        ]
+      },
+      None,
+    )?;
+    Ok(())
+  }
+
+  pub fn claim(
+    &self,
+    key: &str,
+    scope: &str,
+    request: &str,
+    locked_until: UtcDateTime,
+    expires_at: UtcDateTime,
+  ) -> Result<Claim, ApiError> {
+    let record = IdempotencyRecord {
+      key: String::from(key),
+      scope: String::from(scope),
+      request: String::from(request),
+      response: None,
+      locked_until: Some(locked_until),
+      expires_at,
+    };
+    let existing = self.collection.find_one_and_update(
+      doc! {"key": key, "scope": scope},
+      doc! {"$setOnInsert": to_bson(&record)?},
+      FindOneAndUpdateOptions {
+        upsert: Some(true),
+        return_document: Some(ReturnDocument::Before),
+        ..Default::default()
+      },
+    )?;
+    let existing = match existing {
+      Some(document) => from_bson::<IdempotencyRecord>(Bson::Document(document))?,
+      None => return Ok(Claim::New),
+    };
+
+    if existing.expires_at.0 < chrono::Utc::now() {
+      // the ttl monitor has not removed the expired record yet
+      self.collection.replace_one(
+        doc! {"key": key, "scope": scope},
+        match to_bson(&record)? {
+          Bson::Document(document) => document,
+          _ => return Err(ApiError::Internal(String::from("Can not encode idempotency record"))),
+        },
+        None,
+      )?;
+      return Ok(Claim::New);
+    }
+    if existing.request != request {
+      return Err(ApiError::Conflict("idempotency_key_reused"));
+    }
+    match existing.response {
+      Some(response) => Ok(Claim::Replay(response)),
+      None => self.take_over(key, scope, existing.locked_until, locked_until),
+    }
+  }
+
+  // Takes over a request whose lease ran out. Matching on the old lease lets only one of
+  // several concurrent retries win.
+  fn take_over(
+    &self,
+    key: &str,
+    scope: &str,
+    current: Option<UtcDateTime>,
+    locked_until: UtcDateTime,
+  ) -> Result<Claim, ApiError> {
+    if current.map_or(false, |current| current.0 > chrono::Utc::now()) {
+      return Ok(Claim::InProgress);
+    }
+    let current = match current {
+      Some(current) => Bson::UtcDatetime(current.0),
+      None => Bson::Null,
+    };
+    let result = self.collection.update_one(
+      doc! {"key": key, "scope": scope, "response": Bson::Null, "locked_until": current},
+      doc! {"$set": {"locked_until": locked_until.0}},
+      None,
+    )?;
+    if result.modified_count == 1 {
+      Ok(Claim::New)
+    } else {
+      Ok(Claim::InProgress)
+    }
+  }
+
+  pub fn complete(&self, key: &str, scope: &str, response: &StoredResponse) -> Result<(), ApiError> {
+    self.collection.update_one(
+      doc! {"key": key, "scope": scope},
+      doc! {"$set": {"response": to_bson(response)?}},
+      None,
+    )?;
+    Ok(())
+  }
+
+  pub fn release(&self, key: &str, scope: &str) -> Result<(), ApiError> {
+    self.collection.delete_one(doc! {"key": key, "scope": scope, "response": Bson::Null}, None)?;
+    Ok(())
+  }
+}
@@ -1,8 +1,18 @@
-use crate::traits::service::Finder;
-use bson::{doc, oid::ObjectId, ordered};
-use mongodb::Collection;
+use crate::error::ApiError;
+use crate::model::listing::{Listing, ListingQuery, ListingSort};
+use crate::model::page::{page_limit, Cursor, Page};
+use crate::service::session::Transaction;
+use crate::traits::service::{Creator, Finder};
+use bson::{doc, oid::ObjectId, ordered, ordered::OrderedDocument, to_bson, Bson};
+use mongodb::{
+  options::FindOptions,
+  results::{DeleteResult, InsertOneResult, UpdateResult},
+  Collection,
+};
+use std::collections::HashSet;
 use std::vec;
 
+#[derive(Clone)]
 pub struct ListingService {
   collection: Collection,
 }
@@ -11,10 +21,30 @@ impl ListingService {
   pub fn new(collection: Collection) -> Self {
     ListingService { collection }
   }
-  pub fn get_for_homepage(&self) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, String> {
-    let pipeline = vec![
+  pub fn get_for_homepage(&self, query: &ListingQuery) -> Result<Page<OrderedDocument>, ApiError> {
+    self.get_page(doc! {"visible": true, "homepage": true}, query)
+  }
+
+  pub fn get_for_seller(
+    &self,
+    seller: &ObjectId,
+    query: &ListingQuery,
+  ) -> Result<Page<OrderedDocument>, ApiError> {
+    self.get_page(doc! {"visible": true, "seller_id": seller.clone()}, query)
+  }
+
+  fn get_page(&self, mut filter: OrderedDocument, query: &ListingQuery) -> Result<Page<OrderedDocument>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    if let Some(seller) = &query.seller {
+      filter.insert("seller_id", (**seller).clone());
+    }
+    if let Some(kind) = &query.kind {
+      filter.insert("type", kind.as_str());
+    }
+
+    let mut pipeline = vec![
       doc! {
-        "$match": doc! {"visible": true, "homepage": true}
+        "$match": filter
       },
       doc! {
         "$lookup": doc! {"from": "product", "localField": "product_id", "foreignField": "_id", "as": "product"}
@@ -22,35 +52,184 @@ impl ListingService {
       doc! {
         "$unwind": doc! {"path": "$product", "preserveNullAndEmptyArrays": true}
       },
+    ];
+
+    let mut price = doc! {};
+    if let Some(min_price) = query.min_price {
+      price.insert("$gte", min_price);
+    }
+    if let Some(max_price) = query.max_price {
+      price.insert("$lte", max_price);
+    }
+    if !price.is_empty() {
+      pipeline.push(doc! {"$match": {"product.price": price}});
+    }
+
+    let sort_key = query.sort.unwrap_or(ListingSort::Priority).key();
+    match sort_key {
+      Some((expression, direction)) => {
+        pipeline.push(doc! {
+          "$addFields": {"sort_key": {"$toDouble": {"$ifNull": [expression, 0]}}}
+        });
+        if let Some(after) = &query.after {
+          let operator = if direction < 0 { "$lt" } else { "$gt" };
+          let key = after.key.unwrap_or(0.0);
+          pipeline.push(doc! {
+            "$match": {"$or": [
+              {"sort_key": {operator: key}},
+              {"sort_key": key, "_id": {operator: after.id.clone()}}
+            ]}
+          });
+        }
+        pipeline.push(doc! {"$sort": {"sort_key": direction, "_id": direction}});
+      }
+      None => {
+        if let Some(after) = &query.after {
+          pipeline.push(doc! {"$match": {"_id": {"$lt": after.id.clone()}}});
+        }
+        pipeline.push(doc! {"$sort": {"_id": -1}});
+      }
+    }
+    // one extra document tells whether there is a next page
+    let fetch_limit = limit + 1;
+    pipeline.push(doc! {"$limit": fetch_limit});
+    pipeline.push(doc! {
+      "$project": doc! {"product.name": 1, "product.size": 1, "product.price": 1, "product.old_price": 1, "product._id": 1, "product.image_url": 1, "header": 1, "text": 1, "type": 1, "sort_key": 1}
+    });
+
+    let cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    let mut listings: Vec<OrderedDocument> = vec![];
+    for result in cursor {
+      listings.push(result?);
+    }
+
+    let mut next_cursor = None;
+    if listings.len() as i64 > limit {
+      listings.truncate(limit as usize);
+      if let Some(last) = listings.last() {
+        let key = match sort_key {
+          Some(_sort_key) => Some(last.get_f64("sort_key")?),
+          None => None,
+        };
+        next_cursor = Some(Cursor::new(key, last.get_object_id("_id")?.clone()).encode());
+      }
+    }
+    for listing in listings.iter_mut() {
+      listing.remove("sort_key");
+    }
+    Ok(Page {
+      items: listings,
+      next_cursor,
+    })
+  }
+}
+
+impl ListingService {
+  pub fn find_with_products(&self, ids: Vec<ObjectId>) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, ApiError> {
+    let ids: Vec<Bson> = ids.into_iter().map(Bson::ObjectId).collect();
+    let pipeline = vec![
+      doc! {
+        "$match": doc! {"_id": {"$in": ids}}
+      },
       doc! {
-        "$project": doc! {"product.name": 1, "product.size": 1, "product.price": 1, "product.old_price": 1, "product._id": 1, "product.image_url": 1, "header": 1, "text": 1, "type": 1}
+        "$lookup": doc! {"from": "product", "localField": "product_id", "foreignField": "_id", "as": "product"}
       },
       doc! {
-        "$sort": doc! {"priority": -1}
+        "$unwind": doc! {"path": "$product", "preserveNullAndEmptyArrays": false}
       },
     ];
-    match self.collection.aggregate(pipeline.into_iter(), None) {
-      Ok(cursor) => {
-        let mut listings: Vec<ordered::OrderedDocument> = vec![];
-        for result in cursor {
-          if let Ok(document) = result {
-            listings.push(document);
-          } else {
-            return Err(String::from("Can't find listings"));
-          }
-        }
-        Ok(listings)
+    let cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    let mut listings: Vec<ordered::OrderedDocument> = vec![];
+    for result in cursor {
+      listings.push(result?);
+    }
+    Ok(listings)
+  }
+}
+
+fn detail_pipeline(query: OrderedDocument) -> Vec<OrderedDocument> {
+  vec![
+    doc! {
+      "$match": query
+    },
+    doc! {
+      "$lookup": doc! {"from": "product", "localField": "product_id", "foreignField": "_id", "as": "product"}
+    },
+    doc! {
+      "$unwind": doc! {"path": "$product", "preserveNullAndEmptyArrays": false}
+    },
+    doc! {
+      "$lookup": doc! {"from": "seller", "localField": "seller_id", "foreignField": "_id", "as": "seller"}
+    },
+    doc! {
+      "$unwind": doc! {"path": "$seller", "preserveNullAndEmptyArrays": true}
+    },
+    doc! {
+      "$project": doc! {
+        "product._id": 1, "product.name": 1, "product.size": 1, "product.price": 1,
+        "product.old_price": 1, "product.image_url": 1, "seller._id": 1, "seller.name": 1,
+        "header": 1, "text": 1, "type": 1, "stock": 1, "priority": 1, "seller_id": 1
       }
-      Err(_e) => Err(String::from("Error while getting listings")),
+    },
+  ]
+}
+
+impl ListingService {
+  pub fn find_with_details(&self, id: &ObjectId) -> Result<Option<OrderedDocument>, ApiError> {
+    let pipeline = detail_pipeline(doc! {"_id": id.clone(), "visible": true});
+    let mut cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    match cursor.next() {
+      Some(result) => Ok(Some(result?)),
+      None => Ok(None),
     }
   }
-  pub fn get_for_seller(
-    &self,
-    seller: &str,
-  ) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, String> {
+
+  pub fn get_for_product(&self, product_id: &ObjectId) -> Result<Vec<OrderedDocument>, ApiError> {
+    let mut pipeline = detail_pipeline(doc! {"product_id": product_id.clone(), "visible": true});
+    pipeline.push(doc! {"$sort": doc! {"priority": -1}});
+    let cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    let mut listings: Vec<OrderedDocument> = vec![];
+    for result in cursor {
+      listings.push(result?);
+    }
+    Ok(listings)
+  }
+
+  pub fn get_searchable(&self) -> Result<Vec<OrderedDocument>, ApiError> {
+    let pipeline = detail_pipeline(doc! {"visible": true});
+    let cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    let mut listings: Vec<OrderedDocument> = vec![];
+    for result in cursor {
+      listings.push(result?);
+    }
+    Ok(listings)
+  }
+
+  // Of `ids`, the listings that are still visible and not sold out.
+  pub fn get_searchable_ids(&self, ids: &[ObjectId]) -> Result<HashSet<ObjectId>, ApiError> {
+    let ids: Vec<Bson> = ids.iter().cloned().map(Bson::ObjectId).collect();
+    let cursor = self.collection.find(
+      doc! {
+        "_id": {"$in": ids},
+        "visible": true,
+        "$or": [{"stock": {"$exists": false}}, {"stock": {"$gt": 0}}]
+      },
+      FindOptions {
+        projection: Some(doc! {"_id": 1}),
+        ..Default::default()
+      },
+    )?;
+    let mut searchable = HashSet::new();
+    for result in cursor {
+      searchable.insert(result?.get_object_id("_id")?.clone());
+    }
+    Ok(searchable)
+  }
+
+  pub fn get_all_for_owner(&self, seller_id: &ObjectId) -> Result<Vec<OrderedDocument>, ApiError> {
     let pipeline = vec![
       doc! {
-        "$match": doc! {"visible": true, "seller_id": ObjectId::with_string(seller).expect("seller_id not valid")}
+        "$match": doc! {"seller_id": seller_id.clone()}
       },
       doc! {
         "$lookup": doc! {"from": "product", "localField": "product_id", "foreignField": "_id", "as": "product"}
@@ -58,38 +237,173 @@ impl ListingService {
       doc! {
         "$unwind": doc! {"path": "$product", "preserveNullAndEmptyArrays": true}
       },
-      doc! {
-        "$project": doc! {"product.name": 1, "product.size": 1, "product.price": 1, "product.old_price": 1, "product._id": 1, "product.image_url": 1, "header": 1, "text": 1, "type": 1}
-      },
       doc! {
         "$sort": doc! {"priority": -1}
       },
     ];
-    match self.collection.aggregate(pipeline.into_iter(), None) {
-      Ok(cursor) => {
-        let mut listings: Vec<ordered::OrderedDocument> = vec![];
-        for result in cursor {
-          if let Ok(document) = result {
-            listings.push(document);
-          } else {
-            return Err(String::from("Can't find listings"));
-          }
-        }
-        Ok(listings)
-      }
-      Err(_e) => Err(String::from("Error while getting listings")),
+    let cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    let mut listings: Vec<OrderedDocument> = vec![];
+    for result in cursor {
+      listings.push(result?);
+    }
+    Ok(listings)
+  }
+
+  pub fn find_owned(&self, id: &ObjectId, seller_id: &ObjectId) -> Result<Option<OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(
+      doc! {"_id": id.clone(), "seller_id": seller_id.clone()},
+      None,
+    )?)
+  }
+
+  pub fn update_owned(&self, listing: &Listing, id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&listing)? {
+      document.insert("updated_at", chrono::Utc::now());
+      Ok(self.collection.update_one(
+        doc! {"_id": id.clone(), "seller_id": listing.seller_id.clone()},
+        doc! {"$set": document},
+        None,
+      )?)
+    } else {
+      Err(ApiError::Internal(String::from("Can not update listing")))
     }
   }
+
+  pub fn delete_owned(&self, id: &ObjectId, seller_id: &ObjectId) -> Result<DeleteResult, ApiError> {
+    Ok(self.collection.delete_one(
+      doc! {"_id": id.clone(), "seller_id": seller_id.clone()},
+      None,
+    )?)
+  }
+
+  pub fn count_for_product(&self, product_id: &ObjectId) -> Result<i64, ApiError> {
+    Ok(self
+      .collection
+      .count_documents(doc! {"product_id": product_id.clone()}, None)?)
+  }
 }
 
-impl Finder for ListingService {
-  fn find(
+impl Creator<Listing> for ListingService {
+  fn create(&self, listing: &Listing) -> Result<InsertOneResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&listing)? {
+      document.insert("created_at", chrono::Utc::now());
+      Ok(self.collection.insert_one(document, None)?)
+    } else {
+      Err(ApiError::Internal(String::from("Can not create listing")))
+    }
+  }
+}
+
+// Listings without a `stock` field are not stock-tracked. For tracked listings `reserved`
+// counts the units held by basket reservations, `own_reserved` of them by the caller.
+fn available_query(listing_id: &ObjectId, count: i32, own_reserved: i32) -> OrderedDocument {
+  doc! {
+    "_id": listing_id.clone(),
+    "stock": {"$exists": true},
+    "$expr": {"$gte": [
+      "$stock",
+      {"$add": [{"$subtract": [{"$ifNull": ["$reserved", 0]}, own_reserved]}, count]}
+    ]}
+  }
+}
+
+fn untracked_query(listing_id: &ObjectId) -> OrderedDocument {
+  doc! {"_id": listing_id.clone(), "stock": {"$exists": false}}
+}
+
+impl ListingService {
+  pub fn reserve(&self, listing_id: &ObjectId, count: i32) -> Result<bool, ApiError> {
+    let update = doc! {"$inc": {"reserved": count}};
+    if self
+      .collection
+      .update_one(available_query(listing_id, count, 0), update.clone(), None)?
+      .matched_count
+      == 1
+    {
+      return Ok(true);
+    }
+    let result = self
+      .collection
+      .update_one(untracked_query(listing_id), update, None)?;
+    Ok(result.matched_count == 1)
+  }
+
+  pub fn release(&self, listing_id: &ObjectId, count: i32) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"_id": listing_id.clone()},
+      doc! {"$inc": {"reserved": -count}},
+      None,
+    )?)
+  }
+
+  // Takes `count` units out of stock, consuming the `own_reserved` units the buyer holds.
+  pub fn decrement_stock_in(
     &self,
-    id: &str,
-  ) -> Result<Option<bson::ordered::OrderedDocument>, mongodb::error::Error> {
-    self.collection.find_one(
-      doc! {"_id": ObjectId::with_string(id).expect("Id not valid")},
+    transaction: &mut Transaction,
+    listing_id: &ObjectId,
+    count: i32,
+    own_reserved: i32,
+  ) -> Result<bool, ApiError> {
+    let response = transaction.run(doc! {
+      "update": self.collection.name(),
+      "updates": [{
+        "q": available_query(listing_id, count, own_reserved),
+        "u": {"$inc": {"stock": -count, "reserved": -own_reserved}}
+      }]
+    })?;
+    if response.get_i32("n").unwrap_or(0) == 1 {
+      return Ok(true);
+    }
+    let response = transaction.run(doc! {
+      "update": self.collection.name(),
+      "updates": [{
+        "q": untracked_query(listing_id),
+        "u": {"$inc": {"reserved": -own_reserved}}
+      }]
+    })?;
+    Ok(response.get_i32("n").unwrap_or(0) == 1)
+  }
+
+  pub fn decrement_stock(
+    &self,
+    listing_id: &ObjectId,
+    count: i32,
+    own_reserved: i32,
+  ) -> Result<bool, ApiError> {
+    if self
+      .collection
+      .update_one(
+        available_query(listing_id, count, own_reserved),
+        doc! {"$inc": {"stock": -count, "reserved": -own_reserved}},
+        None,
+      )?
+      .matched_count
+      == 1
+    {
+      return Ok(true);
+    }
+    let result = self.collection.update_one(
+      untracked_query(listing_id),
+      doc! {"$inc": {"reserved": -own_reserved}},
+      None,
+    )?;
+    Ok(result.matched_count == 1)
+  }
+
+  pub fn restore_stock(&self, listing_id: &ObjectId, count: i32) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"_id": listing_id.clone(), "stock": {"$exists": true}},
+      doc! {"$inc": {"stock": count}},
+      None,
+    )?)
+  }
+}
+
+impl Finder for ListingService {
+  fn find(&self, id: &ObjectId) -> Result<Option<bson::ordered::OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(
+      doc! {"_id": id.clone()},
       None,
-    )
+    )?)
   }
 }
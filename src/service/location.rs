@@ -0,0 +1,117 @@
+use crate::error::ApiError;
+use crate::model::location::{District, Neighborhood};
+use bson::{doc, from_bson, to_bson, Bson};
+use mongodb::options::{FindOneOptions, FindOptions, UpdateOptions};
+use mongodb::Collection;
+
+const LOCATIONS: &str = include_str!("../../data/locations.tsv");
+
+fn parse_id(value: &str, line: &str) -> Result<i32, ApiError> {
+  value
+    .parse::<i32>()
+    .map_err(|_e| ApiError::Internal(format!("Invalid id in locations line: {}", line)))
+}
+
+// Reads the bundled `district_id, district, neighborhood_id, neighborhood` rows.
+fn bundled_districts() -> Result<Vec<District>, ApiError> {
+  let mut districts: Vec<District> = vec![];
+  for line in LOCATIONS.lines() {
+    if line.trim().is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 4 {
+      return Err(ApiError::Internal(format!("Invalid locations line: {}", line)));
+    }
+    let district_id = parse_id(fields[0], line)?;
+    let neighborhood = Neighborhood {
+      id: parse_id(fields[2], line)?,
+      name: String::from(fields[3]),
+    };
+    match districts.iter_mut().find(|district| district.id == district_id) {
+      Some(district) => district.neighborhoods.push(neighborhood),
+      None => districts.push(District {
+        id: district_id,
+        name: String::from(fields[1]),
+        neighborhoods: vec![neighborhood],
+      }),
+    }
+  }
+  Ok(districts)
+}
+
+// Districts are seeded reference data, each embedding its neighborhoods.
+#[derive(Clone)]
+pub struct LocationService {
+  collection: Collection,
+}
+
+impl LocationService {
+  pub fn new(collection: Collection) -> Self {
+    LocationService { collection }
+  }
+
+  // The bundled data is authoritative for the districts it lists; others are left alone.
+  pub fn seed(&self) -> Result<usize, ApiError> {
+    let districts = bundled_districts()?;
+    for district in &districts {
+      self.collection.update_one(
+        doc! {"_id": district.id},
+        doc! {"$set": {"name": district.name.clone(), "neighborhoods": to_bson(&district.neighborhoods)?}},
+        UpdateOptions {
+          upsert: Some(true),
+          ..Default::default()
+        },
+      )?;
+    }
+    Ok(districts.len())
+  }
+
+  pub fn get_districts(&self) -> Result<Vec<District>, ApiError> {
+    let cursor = self.collection.find(
+      None,
+      FindOptions {
+        projection: Some(doc! {"neighborhoods": 0}),
+        sort: Some(doc! {"name": 1}),
+        ..Default::default()
+      },
+    )?;
+    let mut districts: Vec<District> = vec![];
+    for result in cursor {
+      districts.push(from_bson::<District>(Bson::Document(result?))?);
+    }
+    Ok(districts)
+  }
+
+  pub fn find_district(&self, id: i32) -> Result<Option<District>, ApiError> {
+    match self.collection.find_one(doc! {"_id": id}, None)? {
+      Some(document) => Ok(Some(from_bson::<District>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
+  }
+
+  // Only the matching neighborhood is returned, so the pair is valid when a district comes back.
+  pub fn find_pair(&self, district_id: i32, neighborhood_id: i32) -> Result<Option<District>, ApiError> {
+    let document = self.collection.find_one(
+      doc! {"_id": district_id, "neighborhoods.id": neighborhood_id},
+      FindOneOptions {
+        projection: Some(doc! {"name": 1, "neighborhoods.$": 1}),
+        ..Default::default()
+      },
+    )?;
+    match document {
+      Some(document) => Ok(Some(from_bson::<District>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
+  }
+
+  pub fn get_by_ids(&self, ids: &[i32]) -> Result<Vec<District>, ApiError> {
+    let ids: Vec<Bson> = ids.iter().map(|id| Bson::I32(*id)).collect();
+    let cursor = self.collection.find(doc! {"_id": {"$in": ids}}, None)?;
+    let mut districts: Vec<District> = vec![];
+    for result in cursor {
+      districts.push(from_bson::<District>(Bson::Document(result?))?);
+    }
+    Ok(districts)
+  }
+}
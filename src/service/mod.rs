@@ -4,3 +4,21 @@ pub mod basket;
 pub mod address;
 pub mod order;
 pub mod seller;
+pub mod token;
+pub mod session;
+pub mod idempotency;
+pub mod reservation;
+pub mod coupon;
+pub mod product;
+pub mod search;
+pub mod location;
+pub mod delivery_zone;
+
+use mongodb::error::{ErrorKind, WriteFailure};
+
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+  match error.kind.as_ref() {
+    ErrorKind::WriteError(WriteFailure::WriteError(write_error)) => write_error.code == 11000,
+    _ => false,
+  }
+}
@@ -1,7 +1,10 @@
-use crate::model::order::Order;
-use crate::traits::service::{Creator, Getter};
-use bson::{doc, oid::ObjectId, ordered, to_bson, Bson};
-use mongodb::error::{Error, ErrorKind};
+use crate::error::ApiError;
+use crate::model::order::{Order, OrderDocument, OrderQuery, SellerOrder, Status, StatusChange};
+use crate::model::page::{page_limit, Cursor, Page};
+use crate::service::session::Transaction;
+use crate::traits::service::Creator;
+use bson::{doc, from_bson, oid::ObjectId, ordered::OrderedDocument, to_bson, Bson};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
 use mongodb::results::InsertOneResult;
 use mongodb::Collection;
 
@@ -10,54 +13,197 @@ pub struct OrderService {
   collection: Collection,
 }
 
+fn parse_order(document: Option<OrderedDocument>) -> Result<Option<OrderDocument>, ApiError> {
+  match document {
+    Some(document) => Ok(Some(from_bson::<OrderDocument>(Bson::Document(document))?)),
+    None => Ok(None),
+  }
+}
+
 impl OrderService {
   pub fn new(collection: Collection) -> Self {
     OrderService { collection }
   }
 
-  pub fn find(&self, id: &str, user_id: &str) -> Result<Option<bson::ordered::OrderedDocument>, Error> {
-    self.collection.find_one(
-      doc! {"_id": ObjectId::with_string(id).expect("Id not valid"), "user_id": ObjectId::with_string(user_id).expect("user_id not valid")},
+  pub fn find(&self, id: &ObjectId, user_id: &ObjectId) -> Result<Option<OrderDocument>, ApiError> {
+    parse_order(self.collection.find_one(
+      doc! {"_id": id.clone(), "user_id": user_id.clone()},
       None,
-    )
+    )?)
   }
-}
 
-impl Creator<Order> for OrderService {
-  fn create(&self, order: &Order) -> Result<InsertOneResult, Error> {
-    let serialized_order = to_bson(&order).unwrap();
-    if let Bson::Document(mut document) = serialized_order {
-      document.insert("created_at", chrono::Utc::now());
-      match self.collection.insert_one(document, None) {
-        Ok(insert_result) => Ok(insert_result),
-        Err(e) => Err(e),
-      }
-    } else {
-      Err(Error::from(ErrorKind::OperationError {
-        message: String::from("Can not create order"),
-      }))
+  pub fn find_by_id(&self, id: &ObjectId) -> Result<Option<OrderDocument>, ApiError> {
+    parse_order(self.collection.find_one(doc! {"_id": id.clone()}, None)?)
+  }
+
+  pub fn find_for_seller(
+    &self,
+    id: &ObjectId,
+    seller_id: &ObjectId,
+  ) -> Result<Option<OrderDocument>, ApiError> {
+    parse_order(self.collection.find_one(
+      doc! {"_id": id.clone(), "sub_orders.seller_id": seller_id.clone()},
+      None,
+    )?)
+  }
+
+  pub fn get_page(&self, user_id: &ObjectId, query: &OrderQuery) -> Result<Page<OrderDocument>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    let mut filter = doc! {"user_id": user_id.clone()};
+    if let Some(status) = query.status {
+      filter.insert("status", doc! {"$in": [status.name(), status as i32]});
+    }
+    if let Some(after) = &query.after {
+      filter.insert("_id", doc! {"$lt": after.id.clone()});
+    }
+    let cursor = self.collection.find(
+      filter,
+      FindOptions {
+        sort: Some(doc! {"_id": -1}),
+        limit: Some(limit + 1),
+        ..Default::default()
+      },
+    )?;
+    let mut orders: Vec<OrderDocument> = vec![];
+    for result in cursor {
+      orders.push(from_bson::<OrderDocument>(Bson::Document(result?))?);
+    }
+
+    let mut next_cursor = None;
+    if orders.len() as i64 > limit {
+      orders.truncate(limit as usize);
+      next_cursor = orders
+        .last()
+        .map(|order| Cursor::new(None, order.id.clone()).encode());
     }
+    Ok(Page {
+      items: orders,
+      next_cursor,
+    })
+  }
+
+  pub fn update_status(
+    &self,
+    id: &ObjectId,
+    current: Status,
+    next: Status,
+    changed_by: &ObjectId,
+  ) -> Result<Option<OrderDocument>, ApiError> {
+    let status_change = to_bson(&StatusChange::new(next, changed_by.clone()))?;
+    // matching on the current status keeps concurrent transitions from overwriting each other
+    parse_order(self.collection.find_one_and_update(
+      doc! {"_id": id.clone(), "status": {"$in": [current.name(), current as i32]}},
+      doc! {
+        "$set": {"status": next.name(), "updated_at": chrono::Utc::now()},
+        "$push": {"status_history": status_change}
+      },
+      FindOneAndUpdateOptions {
+        return_document: Some(ReturnDocument::After),
+        ..Default::default()
+      },
+    )?)
   }
-}
 
-impl Getter for OrderService {
-  fn get_all(&self, id: &str) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, String> {
-    match self.collection.find(
-      doc! {"user_id": ObjectId::with_string(id).expect("user_id is not valid")},
+  pub fn update_sub_order_status(
+    &self,
+    id: &ObjectId,
+    seller_id: &ObjectId,
+    current: Status,
+    next: Status,
+    changed_by: &ObjectId,
+  ) -> Result<Option<OrderDocument>, ApiError> {
+    let status_change = to_bson(&StatusChange::new(next, changed_by.clone()))?;
+    parse_order(self.collection.find_one_and_update(
+      doc! {
+        "_id": id.clone(),
+        "sub_orders": {"$elemMatch": {"seller_id": seller_id.clone(), "status": current.name()}}
+      },
+      doc! {
+        "$set": {"sub_orders.$.status": next.name(), "updated_at": chrono::Utc::now()},
+        "$push": {"sub_orders.$.status_history": status_change}
+      },
+      FindOneAndUpdateOptions {
+        return_document: Some(ReturnDocument::After),
+        ..Default::default()
+      },
+    )?)
+  }
+
+  pub fn get_for_seller(&self, seller_id: &ObjectId) -> Result<Vec<SellerOrder>, ApiError> {
+    let pipeline = vec![
+      doc! {
+        "$match": doc! {"sub_orders.seller_id": seller_id.clone()}
+      },
+      doc! {
+        "$unwind": doc! {"path": "$sub_orders"}
+      },
+      doc! {
+        "$match": doc! {"sub_orders.seller_id": seller_id.clone()}
+      },
+      doc! {
+        "$project": doc! {"address": 1, "created_at": 1, "sub_order": "$sub_orders"}
+      },
+      doc! {
+        "$sort": doc! {"created_at": -1}
+      },
+    ];
+    let cursor = self.collection.aggregate(pipeline.into_iter(), None)?;
+    let mut orders: Vec<SellerOrder> = vec![];
+    for result in cursor {
+      orders.push(from_bson::<SellerOrder>(Bson::Document(result?))?);
+    }
+    Ok(orders)
+  }
+
+  pub fn count_coupon_uses(&self, coupon_id: &ObjectId, user_id: &ObjectId) -> Result<i64, ApiError> {
+    Ok(self.collection.count_documents(
+      doc! {
+        "user_id": user_id.clone(),
+        "discounts.coupon_id": coupon_id.clone(),
+        "status": {"$ne": Status::Cancelled.name()}
+      },
       None,
-    ) {
-      Ok(cursor) => {
-        let mut orders: Vec<ordered::OrderedDocument> = vec![];
-        for result in cursor {
-          if let Ok(document) = result {
-            orders.push(document);
-          } else {
-            return Err(String::from("Can't find orders"));
-          }
-        }
-        Ok(orders)
-      }
-      Err(_e) => Err(String::from("Error while getting orders")),
+    )?)
+  }
+
+  pub fn migrate_legacy_status(&self) -> Result<i64, ApiError> {
+    let mut modified_count = 0;
+    for status in Status::ALL.iter() {
+      let result = self.collection.update_many(
+        doc! {"status": *status as i32},
+        doc! {"$set": {"status": status.name()}},
+        None,
+      )?;
+      modified_count += result.modified_count;
     }
+    Ok(modified_count)
+  }
+}
+
+fn order_document(order: &Order) -> Result<OrderedDocument, ApiError> {
+  if let Bson::Document(mut document) = to_bson(&order)? {
+    document.insert("created_at", chrono::Utc::now());
+    Ok(document)
+  } else {
+    Err(ApiError::Internal(String::from("Can not create order")))
+  }
+}
+
+impl OrderService {
+  pub fn create_in(&self, transaction: &mut Transaction, order: &Order) -> Result<ObjectId, ApiError> {
+    let id = ObjectId::new().map_err(|e| ApiError::Internal(format!("Can not generate id: {}", e)))?;
+    let mut document = order_document(order)?;
+    document.insert("_id", id.clone());
+    transaction.run(doc! {
+      "insert": self.collection.name(),
+      "documents": [document]
+    })?;
+    Ok(id)
+  }
+}
+
+impl Creator<Order> for OrderService {
+  fn create(&self, order: &Order) -> Result<InsertOneResult, ApiError> {
+    Ok(self.collection.insert_one(order_document(order)?, None)?)
   }
 }
@@ -0,0 +1,62 @@
+use crate::error::ApiError;
+use crate::model::listing::Product;
+use crate::traits::service::{Finder, Updater};
+use bson::{doc, oid::ObjectId, ordered::OrderedDocument, to_bson, Bson};
+use mongodb::{
+  results::{DeleteResult, InsertOneResult, UpdateResult},
+  Collection,
+};
+
+#[derive(Clone)]
+pub struct ProductService {
+  collection: Collection,
+}
+
+impl ProductService {
+  pub fn new(collection: Collection) -> Self {
+    ProductService { collection }
+  }
+
+  pub fn create_with_id(&self, id: &ObjectId, product: &Product) -> Result<InsertOneResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&product)? {
+      document.insert("_id", id.clone());
+      document.insert("created_at", chrono::Utc::now());
+      Ok(self.collection.insert_one(document, None)?)
+    } else {
+      Err(ApiError::Internal(String::from("Can not create product")))
+    }
+  }
+
+  pub fn delete(&self, id: &ObjectId) -> Result<DeleteResult, ApiError> {
+    Ok(self.collection.delete_one(doc! {"_id": id.clone()}, None)?)
+  }
+}
+
+impl Updater<Product> for ProductService {
+  fn update(&self, product: &Product, id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    if let Bson::Document(document) = to_bson(&product)? {
+      // cleared fields are removed rather than stored as null
+      let mut set = doc! {"updated_at": chrono::Utc::now()};
+      let mut unset = doc! {};
+      for (key, value) in document {
+        match value {
+          Bson::Null => unset.insert(key, ""),
+          value => set.insert(key, value),
+        };
+      }
+      let mut update = doc! {"$set": set};
+      if !unset.is_empty() {
+        update.insert("$unset", unset);
+      }
+      Ok(self.collection.update_one(doc! {"_id": id.clone()}, update, None)?)
+    } else {
+      Err(ApiError::Internal(String::from("Can not update product")))
+    }
+  }
+}
+
+impl Finder for ProductService {
+  fn find(&self, id: &ObjectId) -> Result<Option<OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(doc! {"_id": id.clone()}, None)?)
+  }
+}
@@ -0,0 +1,148 @@
+use crate::error::ApiError;
+use crate::model::reservation::Reservation;
+use crate::service::is_duplicate_key;
+use crate::service::session::Transaction;
+use bson::{doc, from_bson, oid::ObjectId, ordered::OrderedDocument, Bson, UtcDateTime};
+use mongodb::options::UpdateOptions;
+use mongodb::{results::UpdateResult, Collection, Database};
+
+#[derive(Clone)]
+pub struct ReservationService {
+  collection: Collection,
+}
+
+fn reservation_count(document: Option<OrderedDocument>) -> Result<i32, ApiError> {
+  match document {
+    Some(document) => Ok(from_bson::<Reservation>(Bson::Document(document))?.count),
+    None => Ok(0),
+  }
+}
+
+impl ReservationService {
+  pub fn new(collection: Collection) -> Self {
+    ReservationService { collection }
+  }
+
+  // One reservation per user and listing, `swap` relies on it to detect concurrent inserts.
+  pub fn ensure_indexes(&self, database: &Database) -> Result<(), ApiError> {
+    database.run_command(
+      doc! {
+        "createIndexes": self.collection.name(),
+        "indexes": [{
+          "key": {"listing_id": 1, "user_id": 1},
+          "name": "listing_id_user_id",
+          "unique": true
+        }]
+      },
+      None,
+    )?;
+    Ok(())
+  }
+
+  pub fn count_for(&self, listing_id: &ObjectId, user_id: &ObjectId) -> Result<i32, ApiError> {
+    reservation_count(self.collection.find_one(
+      doc! {"listing_id": listing_id.clone(), "user_id": user_id.clone()},
+      None,
+    )?)
+  }
+
+  // Moves the user's reservation from `held` to `count` units. Returns false without writing
+  // anything when the reservation no longer holds `held`, as another request changed it first.
+  pub fn swap(
+    &self,
+    listing_id: &ObjectId,
+    user_id: &ObjectId,
+    held: i32,
+    count: i32,
+    expires_at: UtcDateTime,
+  ) -> Result<bool, ApiError> {
+    let filter = doc! {"listing_id": listing_id.clone(), "user_id": user_id.clone(), "count": held};
+    if held == 0 {
+      if count == 0 {
+        return Ok(true);
+      }
+      let reservation = doc! {
+        "listing_id": listing_id.clone(),
+        "user_id": user_id.clone(),
+        "count": count,
+        "expires_at": expires_at.0
+      };
+      match self.collection.insert_one(reservation, None) {
+        Ok(_) => Ok(true),
+        Err(e) if is_duplicate_key(&e) => Ok(false),
+        Err(e) => Err(e.into()),
+      }
+    } else if count == 0 {
+      Ok(self.collection.delete_one(filter, None)?.deleted_count == 1)
+    } else {
+      let result = self.collection.update_one(
+        filter,
+        doc! {"$set": {"count": count, "expires_at": expires_at.0}},
+        None,
+      )?;
+      Ok(result.matched_count == 1)
+    }
+  }
+
+  pub fn add(
+    &self,
+    listing_id: &ObjectId,
+    user_id: &ObjectId,
+    count: i32,
+    expires_at: UtcDateTime,
+  ) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"listing_id": listing_id.clone(), "user_id": user_id.clone()},
+      doc! {"$inc": {"count": count}, "$set": {"expires_at": expires_at.0}},
+      UpdateOptions {
+        upsert: Some(true),
+        ..Default::default()
+      },
+    )?)
+  }
+
+  // Removes the reservation and returns how many units it was holding.
+  pub fn take(&self, listing_id: &ObjectId, user_id: &ObjectId) -> Result<i32, ApiError> {
+    reservation_count(self.collection.find_one_and_delete(
+      doc! {"listing_id": listing_id.clone(), "user_id": user_id.clone()},
+      None,
+    )?)
+  }
+
+  pub fn take_in(
+    &self,
+    transaction: &mut Transaction,
+    listing_id: &ObjectId,
+    user_id: &ObjectId,
+  ) -> Result<i32, ApiError> {
+    let response = transaction.run(doc! {
+      "findAndModify": self.collection.name(),
+      "query": {"listing_id": listing_id.clone(), "user_id": user_id.clone()},
+      "remove": true
+    })?;
+    match response.get("value") {
+      Some(Bson::Document(document)) => reservation_count(Some(document.clone())),
+      _ => Ok(0),
+    }
+  }
+
+  pub fn take_expired(&self, listing_id: &ObjectId) -> Result<Option<i32>, ApiError> {
+    match self.collection.find_one_and_delete(
+      doc! {"listing_id": listing_id.clone(), "expires_at": {"$lt": chrono::Utc::now()}},
+      None,
+    )? {
+      Some(document) => Ok(Some(reservation_count(Some(document))?)),
+      None => Ok(None),
+    }
+  }
+
+  pub fn take_any(&self, user_id: &ObjectId) -> Result<Option<Reservation>, ApiError> {
+    match self
+      .collection
+      .find_one_and_delete(doc! {"user_id": user_id.clone()}, None)?
+    {
+      Some(document) => Ok(Some(from_bson::<Reservation>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
+  }
+}
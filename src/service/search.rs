@@ -0,0 +1,316 @@
+use crate::error::ApiError;
+use crate::model::page::page_limit;
+use crate::model::search::{Facet, Facets, SearchQuery, SearchResult};
+use crate::service::listing::ListingService;
+use bson::{oid::ObjectId, ordered::OrderedDocument, Bson};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+const NAME_WEIGHT: f64 = 3.0;
+const HEADER_WEIGHT: f64 = 2.0;
+const TEXT_WEIGHT: f64 = 1.0;
+const PRIORITY_WEIGHT: f64 = 0.25;
+
+// Lowercases with Turkish casing and folds diacritics, so "IŞIK", "ışık" and "isik" match.
+fn fold(text: &str) -> String {
+  text
+    .chars()
+    .map(|c| match c {
+      'I' | 'İ' | 'ı' | 'î' | 'Î' => 'i',
+      'Ş' | 'ş' => 's',
+      'Ç' | 'ç' => 'c',
+      'Ğ' | 'ğ' => 'g',
+      'Ö' | 'ö' => 'o',
+      'Ü' | 'ü' | 'û' | 'Û' => 'u',
+      'Â' | 'â' => 'a',
+      c if c.is_alphanumeric() => c.to_lowercase().next().unwrap_or(c),
+      _ => ' ',
+    })
+    .collect()
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+  fold(text).split_whitespace().map(String::from).collect()
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  for (i, a_char) in a.iter().enumerate() {
+    let mut current = vec![i + 1; b.len() + 1];
+    for (j, b_char) in b.iter().enumerate() {
+      let substitution = previous[j] + if a_char == b_char { 0 } else { 1 };
+      current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+    }
+    previous = current;
+  }
+  previous[b.len()]
+}
+
+// How well a single query term matches a single indexed token, between 0 and 1.
+fn term_score(term: &[char], token: &[char]) -> f64 {
+  if term == token {
+    return 1.0;
+  }
+  if term.len() >= 2 && token.starts_with(term) {
+    return 0.8;
+  }
+  let allowed_typos = match term.len() {
+    0..=3 => 0,
+    4..=7 => 1,
+    _ => 2,
+  };
+  if allowed_typos > 0
+    && (term.len() as i64 - token.len() as i64).abs() as usize <= allowed_typos
+    && edit_distance(term, token) <= allowed_typos
+  {
+    return 0.6;
+  }
+  0.0
+}
+
+struct Entry {
+  id: ObjectId,
+  listing: OrderedDocument,
+  seller_id: String,
+  kind: String,
+  priority: f64,
+  fields: Vec<(f64, Vec<Vec<char>>)>,
+}
+
+impl Entry {
+  fn new(listing: OrderedDocument) -> Result<Entry, ApiError> {
+    let name = listing
+      .get_document("product")
+      .ok()
+      .and_then(|product| product.get_str("name").ok())
+      .unwrap_or_default()
+      .to_string();
+    let chars = |text: &str| -> Vec<Vec<char>> {
+      tokenize(text).iter().map(|token| token.chars().collect()).collect()
+    };
+    let fields = vec![
+      (NAME_WEIGHT, chars(&name)),
+      (HEADER_WEIGHT, chars(listing.get_str("header").unwrap_or_default())),
+      (TEXT_WEIGHT, chars(listing.get_str("text").unwrap_or_default())),
+    ];
+    Ok(Entry {
+      id: listing.get_object_id("_id")?.clone(),
+      seller_id: listing
+        .get_object_id("seller_id")
+        .map(|id| id.to_hex())
+        .unwrap_or_default(),
+      kind: listing.get_str("type").unwrap_or_default().to_string(),
+      priority: match listing.get("priority") {
+        Some(Bson::I32(priority)) => *priority as f64,
+        Some(Bson::I64(priority)) => *priority as f64,
+        Some(Bson::FloatingPoint(priority)) => *priority,
+        _ => 0.0,
+      },
+      fields,
+      listing,
+    })
+  }
+
+  // Every term has to match somewhere; each counts with its best weighted match.
+  fn relevance(&self, terms: &[Vec<char>]) -> Option<f64> {
+    let mut relevance = 0.0;
+    for term in terms {
+      let best = self
+        .fields
+        .iter()
+        .flat_map(|(weight, tokens)| tokens.iter().map(move |token| weight * term_score(term, token)))
+        .fold(0.0, f64::max);
+      if best <= 0.0 {
+        return None;
+      }
+      relevance += best;
+    }
+    Some(relevance)
+  }
+}
+
+struct SearchIndex {
+  entries: Vec<Entry>,
+  built_at: DateTime<Utc>,
+}
+
+// The index is built from visible listings and shared by all workers; it is rebuilt lazily
+// once it is older than SEARCH_INDEX_TTL_SECONDS. Matches are checked against the listings
+// before they are counted, so hidden, deleted and sold out ones drop out right away.
+#[derive(Clone)]
+pub struct SearchService {
+  listing: ListingService,
+  index: Arc<RwLock<Option<SearchIndex>>>,
+}
+
+fn facet_counts(counts: HashMap<&str, usize>) -> Vec<Facet> {
+  let mut facets: Vec<Facet> = counts
+    .into_iter()
+    .filter(|(value, _count)| !value.is_empty())
+    .map(|(value, count)| Facet {
+      value: value.to_string(),
+      count,
+    })
+    .collect();
+  facets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+  facets
+}
+
+impl SearchService {
+  pub fn new(listing: ListingService) -> Self {
+    SearchService {
+      listing,
+      index: Arc::new(RwLock::new(None)),
+    }
+  }
+
+  fn is_fresh(&self) -> bool {
+    let ttl = dotenv!("SEARCH_INDEX_TTL_SECONDS")
+      .parse::<i64>()
+      .expect("SEARCH_INDEX_TTL_SECONDS is not a number");
+    match &*self.index.read().unwrap_or_else(|e| e.into_inner()) {
+      Some(index) => Utc::now() - index.built_at < chrono::Duration::seconds(ttl),
+      None => false,
+    }
+  }
+
+  fn refresh(&self) -> Result<(), ApiError> {
+    let entries = self
+      .listing
+      .get_searchable()?
+      .into_iter()
+      .map(Entry::new)
+      .collect::<Result<_, ApiError>>()?;
+    *self.index.write().unwrap_or_else(|e| e.into_inner()) = Some(SearchIndex {
+      entries,
+      built_at: Utc::now(),
+    });
+    Ok(())
+  }
+
+  pub fn search(&self, query: &SearchQuery) -> Result<SearchResult, ApiError> {
+    let limit = page_limit(query.limit)? as usize;
+    let terms: Vec<Vec<char>> = tokenize(&query.q)
+      .iter()
+      .map(|term| term.chars().collect())
+      .collect();
+    if terms.is_empty() {
+      return Err(ApiError::Validation(String::from("q must contain a word to search for")));
+    }
+    if !self.is_fresh() {
+      self.refresh()?;
+    }
+
+    let guard = self.index.read().unwrap_or_else(|e| e.into_inner());
+    let entries = match &*guard {
+      Some(index) => &index.entries,
+      None => return Err(ApiError::Internal(String::from("Search index is not built"))),
+    };
+    let mut matches: Vec<(f64, &Entry)> = entries
+      .iter()
+      .filter_map(|entry| {
+        entry.relevance(&terms).map(|relevance| {
+          let boost = PRIORITY_WEIGHT * (1.0 + entry.priority.max(0.0)).ln();
+          (relevance + boost, entry)
+        })
+      })
+      .collect();
+    let ids: Vec<ObjectId> = matches.iter().map(|(_score, entry)| entry.id.clone()).collect();
+    let searchable = self.listing.get_searchable_ids(&ids)?;
+    matches.retain(|(_score, entry)| searchable.contains(&entry.id));
+
+    let mut sellers: HashMap<&str, usize> = HashMap::new();
+    let mut types: HashMap<&str, usize> = HashMap::new();
+    for (_score, entry) in &matches {
+      *sellers.entry(entry.seller_id.as_str()).or_insert(0) += 1;
+      *types.entry(entry.kind.as_str()).or_insert(0) += 1;
+    }
+
+    let seller = query.seller.as_ref().map(|seller| seller.to_hex());
+    let mut hits: Vec<(f64, &Entry)> = matches
+      .into_iter()
+      .filter(|(_score, entry)| seller.as_ref().map_or(true, |seller| &entry.seller_id == seller))
+      .filter(|(_score, entry)| query.kind.as_ref().map_or(true, |kind| &entry.kind == kind))
+      .collect();
+    hits.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let total = hits.len();
+    let items = hits
+      .into_iter()
+      .skip(query.offset.unwrap_or(0))
+      .take(limit)
+      .map(|(score, entry)| {
+        let mut listing = entry.listing.clone();
+        listing.insert("score", score);
+        listing
+      })
+      .collect();
+    Ok(SearchResult {
+      items,
+      total,
+      facets: Facets {
+        sellers: facet_counts(sellers),
+        types: facet_counts(types),
+      },
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn chars(text: &str) -> Vec<char> {
+    text.chars().collect()
+  }
+
+  #[test]
+  fn fold_lowercases_turkish_i_forms_to_plain_i() {
+    assert_eq!(fold("IŞIK"), "isik");
+    assert_eq!(fold("ışık"), "isik");
+    assert_eq!(fold("İstanbul"), "istanbul");
+    assert_eq!(fold("Iğdır"), "igdir");
+  }
+
+  #[test]
+  fn fold_drops_diacritics() {
+    assert_eq!(fold("ŞĞÜÇÖ şğüçö"), "sguco sguco");
+    assert_eq!(fold("Kâğıt Hûn"), "kagit hun");
+  }
+
+  #[test]
+  fn fold_turns_punctuation_into_spaces() {
+    assert_eq!(fold("süt-kakao,1L"), "sut kakao 1l");
+  }
+
+  #[test]
+  fn tokenize_splits_folded_words() {
+    assert_eq!(tokenize("  Çay,  ŞEKER!  "), vec!["cay", "seker"]);
+    assert!(tokenize(" - , ").is_empty());
+  }
+
+  #[test]
+  fn edit_distance_counts_single_char_edits() {
+    assert_eq!(edit_distance(&chars("kitap"), &chars("kitap")), 0);
+    assert_eq!(edit_distance(&chars("kitap"), &chars("kitab")), 1);
+    assert_eq!(edit_distance(&chars("sekr"), &chars("seker")), 1);
+    assert_eq!(edit_distance(&chars("seker"), &chars("sekr")), 1);
+    assert_eq!(edit_distance(&chars(""), &chars("cay")), 3);
+    assert_eq!(edit_distance(&chars("ab"), &chars("ba")), 2);
+  }
+
+  #[test]
+  fn edit_distance_compares_chars_not_bytes() {
+    assert_eq!(edit_distance(&chars("şeker"), &chars("seker")), 1);
+  }
+
+  #[test]
+  fn term_score_ranks_exact_then_prefix_then_typo() {
+    assert_eq!(term_score(&chars("cay"), &chars("cay")), 1.0);
+    assert_eq!(term_score(&chars("ca"), &chars("cay")), 0.8);
+    assert_eq!(term_score(&chars("sekar"), &chars("seker")), 0.6);
+    // short terms have to match exactly or as a prefix
+    assert_eq!(term_score(&chars("cey"), &chars("cay")), 0.0);
+  }
+}
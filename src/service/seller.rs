@@ -1,5 +1,6 @@
+use crate::error::ApiError;
 use bson::{doc, ordered};
-use mongodb::{error::Error, Collection};
+use mongodb::Collection;
 
 pub struct SellerService {
   collection: Collection,
@@ -10,7 +11,7 @@ impl SellerService {
     SellerService { collection }
   }
 
-  pub fn get(&self, name: &str) -> Result<Option<ordered::OrderedDocument>, Error> {
-    self.collection.find_one(doc! {"name": name}, None)
+  pub fn get(&self, name: &str) -> Result<Option<ordered::OrderedDocument>, ApiError> {
+    Ok(self.collection.find_one(doc! {"name": name}, None)?)
   }
 }
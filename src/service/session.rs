@@ -0,0 +1,88 @@
+use crate::error::ApiError;
+use bson::{doc, ordered::OrderedDocument, spec::BinarySubtype, Bson};
+use mongodb::error::ErrorKind;
+use mongodb::Database;
+use rand::Rng;
+
+// The driver has no session API yet, so transactions are driven through raw commands
+// carrying an explicit logical session id and transaction number.
+#[derive(Clone)]
+pub struct SessionService {
+  database: Database,
+  admin: Database,
+}
+
+impl SessionService {
+  pub fn new(database: Database, admin: Database) -> Self {
+    SessionService { database, admin }
+  }
+
+  pub fn start_transaction(&self) -> Transaction {
+    let session_id: Vec<u8> = (0..16).map(|_| rand::thread_rng().gen::<u8>()).collect();
+    Transaction {
+      database: self.database.clone(),
+      admin: self.admin.clone(),
+      lsid: doc! {"id": Bson::Binary(BinarySubtype::Uuid, session_id)},
+      started: false,
+    }
+  }
+}
+
+pub struct Transaction {
+  database: Database,
+  admin: Database,
+  lsid: OrderedDocument,
+  started: bool,
+}
+
+impl Transaction {
+  fn with_session(&self, mut command: OrderedDocument) -> OrderedDocument {
+    command.insert("lsid", self.lsid.clone());
+    command.insert("txnNumber", Bson::I64(1));
+    command.insert("autocommit", false);
+    command
+  }
+
+  pub fn run(&mut self, command: OrderedDocument) -> Result<OrderedDocument, ApiError> {
+    let mut command = self.with_session(command);
+    if !self.started {
+      command.insert("startTransaction", true);
+    }
+    let response = self.database.run_command(command, None)?;
+    self.started = true;
+    if let Ok(write_errors) = response.get_array("writeErrors") {
+      if !write_errors.is_empty() {
+        return Err(ApiError::Internal(format!("Write error in transaction: {:?}", write_errors)));
+      }
+    }
+    Ok(response)
+  }
+
+  pub fn commit(self) -> Result<(), ApiError> {
+    let command = self.with_session(doc! {"commitTransaction": 1});
+    self.admin.run_command(command, None)?;
+    Ok(())
+  }
+
+  pub fn abort(self) {
+    if self.started {
+      let command = self.with_session(doc! {"abortTransaction": 1});
+      if let Err(e) = self.admin.run_command(command, None) {
+        log::warn!("Can not abort transaction: {:?}", e);
+      }
+    }
+  }
+}
+
+pub fn is_transaction_unsupported(error: &ApiError) -> bool {
+  match error {
+    // 20: IllegalOperation on standalone servers, 263: OperationNotSupportedInTransaction
+    ApiError::Database(e) => match e.kind.as_ref() {
+      ErrorKind::CommandError(command_error) => {
+        command_error.code == 20 || command_error.code == 263
+      }
+      _ => false,
+    },
+    _ => false,
+  }
+}
@@ -0,0 +1,90 @@
+use crate::error::ApiError;
+use crate::model::token::RefreshToken;
+use bson::{doc, from_bson, oid::ObjectId, to_bson, Bson, UtcDateTime};
+use mongodb::{
+  results::{InsertOneResult, UpdateResult},
+  Collection, Database,
+};
+
+#[derive(Clone)]
+pub struct TokenService {
+  collection: Collection,
+}
+
+impl TokenService {
+  pub fn new(collection: Collection) -> Self {
+    TokenService { collection }
+  }
+
+  pub fn create_refresh(&self, refresh_token: &RefreshToken) -> Result<InsertOneResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&refresh_token)? {
+      document.insert("kind", "refresh");
+      document.insert("revoked", false);
+      document.insert("created_at", chrono::Utc::now());
+      Ok(self.collection.insert_one(document, None)?)
+    } else {
+      Err(ApiError::Internal(String::from("Can not create refresh token")))
+    }
+  }
+
+  // Revoked access tokens are looked up on every authenticated request.
+  pub fn ensure_indexes(&self, database: &Database) -> Result<(), ApiError> {
+    database.run_command(
+      doc! {
+        "createIndexes": self.collection.name(),
+        "indexes": [
+          {"key": {"token": 1}, "name": "token", "partialFilterExpression": {"kind": "refresh"}},
+          {"key": {"jti": 1}, "name": "jti", "partialFilterExpression": {"kind": "revoked_access"}},
+          {"key": {"expires_at": 1}, "name": "expires_at_ttl", "expireAfterSeconds": 0}
+        ]
+      },
+      None,
+    )?;
+    Ok(())
+  }
+
+  // Revokes the token in the same update that finds it, so a token is only ever used once.
+  pub fn use_refresh(&self, token: &str) -> Result<Option<RefreshToken>, ApiError> {
+    let query = doc! {
+      "kind": "refresh",
+      "token": token,
+      "revoked": false,
+      "expires_at": {"$gt": chrono::Utc::now()}
+    };
+    let update = doc! {"$set": {"revoked": true, "updated_at": chrono::Utc::now()}};
+    match self.collection.find_one_and_update(query, update, None)? {
+      Some(document) => Ok(Some(from_bson::<RefreshToken>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
+  }
+
+  pub fn revoke_refresh(&self, token: &str) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"kind": "refresh", "token": token},
+      doc! {"$set": {"revoked": true, "updated_at": chrono::Utc::now()}},
+      None,
+    )?)
+  }
+
+  pub fn revoke_all_refresh(&self, user_id: &ObjectId) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_many(
+      doc! {"kind": "refresh", "user_id": user_id.clone(), "revoked": false},
+      doc! {"$set": {"revoked": true, "updated_at": chrono::Utc::now()}},
+      None,
+    )?)
+  }
+
+  pub fn revoke_access(&self, jti: &str, expires_at: UtcDateTime) -> Result<InsertOneResult, ApiError> {
+    Ok(self.collection.insert_one(
+      doc! {"kind": "revoked_access", "jti": jti, "expires_at": expires_at.0, "created_at": chrono::Utc::now()},
+      None,
+    )?)
+  }
+
+  pub fn is_access_revoked(&self, jti: &str) -> Result<bool, ApiError> {
+    let revoked = self
+      .collection
+      .find_one(doc! {"kind": "revoked_access", "jti": jti}, None)?;
+    Ok(revoked.is_some())
+  }
+}
@@ -1,8 +1,8 @@
-use crate::model::user::User;
+use crate::error::ApiError;
+use crate::model::user::{User, UserDocument};
 use crate::traits::service::Creator;
-use bson::{doc, oid::ObjectId, ordered::OrderedDocument, to_bson, Bson};
+use bson::{doc, from_bson, oid::ObjectId, to_bson, Bson};
 use mongodb::{
-  error::{Error, ErrorKind},
   results::{InsertOneResult, UpdateResult},
   Collection,
 };
@@ -16,41 +16,53 @@ impl UserService {
   pub fn new(collection: Collection) -> Self {
     UserService { collection }
   }
-  pub fn get(&self, phone: &String) -> Result<Option<OrderedDocument>, Error> {
-    self.collection.find_one(doc! {"phone": phone}, None)
+  pub fn get(&self, phone: &String) -> Result<Option<UserDocument>, ApiError> {
+    match self.collection.find_one(doc! {"phone": phone}, None)? {
+      Some(document) => Ok(Some(from_bson::<UserDocument>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
+  }
+
+  pub fn find(&self, id: &ObjectId) -> Result<Option<UserDocument>, ApiError> {
+    match self.collection.find_one(doc! {"_id": id.clone(), "active": {"$ne": false}}, None)? {
+      Some(document) => Ok(Some(from_bson::<UserDocument>(Bson::Document(document))?)),
+      None => Ok(None),
+    }
   }
 
-  pub fn create_anon(&self) -> Result<InsertOneResult, Error> {
-    self.collection.insert_one(doc! {"created_at": chrono::Utc::now()}, None)
+  pub fn create_anon(&self) -> Result<InsertOneResult, ApiError> {
+    Ok(self.collection.insert_one(doc! {"created_at": chrono::Utc::now()}, None)?)
   }
 
   pub fn register(
     &self,
-    user_id: &str,
+    user_id: &ObjectId,
     phone: &str,
     password: &str,
-  ) -> Result<UpdateResult, Error> {
-    self.collection.update_one(
-      doc! {"_id": ObjectId::with_string(&user_id).expect("Id not valid")},
+  ) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"_id": user_id.clone()},
       doc! {"$set": {"phone": String::from(phone), "password": String::from(password)}},
       None,
-    )
+    )?)
+  }
+
+  pub fn deactivate_guest(&self, guest_id: &ObjectId, merged_into: &ObjectId) -> Result<UpdateResult, ApiError> {
+    Ok(self.collection.update_one(
+      doc! {"_id": guest_id.clone(), "phone": {"$exists": false}},
+      doc! {"$set": {"active": false, "merged_into": merged_into.clone(), "updated_at": chrono::Utc::now()}},
+      None,
+    )?)
   }
 }
 
 impl Creator<User> for UserService {
-  fn create(&self, user: &User) -> Result<InsertOneResult, Error> {
-    let serialized_user = to_bson(&user).unwrap();
-    if let Bson::Document(mut document) = serialized_user {
+  fn create(&self, user: &User) -> Result<InsertOneResult, ApiError> {
+    if let Bson::Document(mut document) = to_bson(&user)? {
       document.insert("created_at", chrono::Utc::now());
-      match self.collection.insert_one(document, None) {
-        Ok(insert_result) => Ok(insert_result),
-        Err(e) => Err(e),
-      }
+      Ok(self.collection.insert_one(document, None)?)
     } else {
-      Err(Error::from(ErrorKind::OperationError {
-        message: String::from("Can not create User"),
-      }))
+      Err(ApiError::Internal(String::from("Can not create User")))
     }
   }
 }
@@ -0,0 +1,37 @@
+use bson::oid::ObjectId;
+use mongodb::{Client, Collection, Database};
+
+// A throwaway database on the local MongoDB at DB_URL, dropped when the test ends.
+pub struct TestDatabase {
+  database: Database,
+}
+
+impl TestDatabase {
+  pub fn new() -> Self {
+    let client = Client::with_uri_str(dotenv!("DB_URL")).expect("Can not connect to MongoDB");
+    let name = format!("{}_test_{}", dotenv!("DB_NAME"), new_id());
+    TestDatabase {
+      database: client.database(&name),
+    }
+  }
+
+  pub fn database(&self) -> &Database {
+    &self.database
+  }
+
+  pub fn collection(&self, name: &str) -> Collection {
+    self.database.collection(name)
+  }
+}
+
+impl Drop for TestDatabase {
+  fn drop(&mut self) {
+    if let Err(e) = self.database.drop(None) {
+      eprintln!("Can not drop test database {}: {:?}", self.database.name(), e);
+    }
+  }
+}
+
+pub fn new_id() -> ObjectId {
+  ObjectId::new().expect("Can not generate id")
+}
@@ -1 +1,2 @@
-pub mod service;
\ No newline at end of file
+pub mod service;
+pub mod validate;
@@ -1,19 +1,20 @@
-use mongodb::error::Error;
+use crate::error::ApiError;
+use bson::oid::ObjectId;
 use mongodb::results::{InsertOneResult, UpdateResult};
 
 pub trait Creator<T> {
-  fn create(&self, model: &T) -> Result<InsertOneResult, Error>;
+  fn create(&self, model: &T) -> Result<InsertOneResult, ApiError>;
 }
 
-pub trait Getter {
-  fn get_all(&self, user_id: &str) -> Result<std::vec::Vec<bson::ordered::OrderedDocument>, String>;
+pub trait Getter<T> {
+  fn get_all(&self, user_id: &ObjectId) -> Result<std::vec::Vec<T>, ApiError>;
 }
 
 
 pub trait Updater<T> {
-  fn update(&self, model: &T, id: &str) -> Result<UpdateResult, Error>;
+  fn update(&self, model: &T, id: &ObjectId) -> Result<UpdateResult, ApiError>;
 }
 
 pub trait Finder {
-  fn find(&self, id: &str) -> Result<Option<bson::ordered::OrderedDocument>, Error>;
+  fn find(&self, id: &ObjectId) -> Result<Option<bson::ordered::OrderedDocument>, ApiError>;
 }
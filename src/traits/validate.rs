@@ -0,0 +1,7 @@
+use crate::model::validation::Validator;
+
+// Request bodies declare their rules here; `Valid<T>` runs them before the handler. Rules may
+// normalize the values they check, such as trimming strings or formatting phone numbers.
+pub trait Validate {
+  fn validate(&mut self, validator: &mut Validator);
+}